@@ -0,0 +1,42 @@
+use std::collections::BTreeSet;
+
+use crate::instruction::Instruction;
+use crate::mmu::{Mmu, PROGRAM_START};
+
+/// Walks every path from `entry` through the loaded ROM and returns the
+/// addresses that can be reached as instructions. Targets of `JumpV0` depend on
+/// runtime state and are not followed.
+pub fn reachable_code(mmu: &Mmu, entry: u16) -> BTreeSet<u16> {
+    let end = mmu.rom_end();
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![entry];
+
+    while let Some(address) = pending.pop() {
+        if address < PROGRAM_START || address + 1 >= end || !visited.insert(address) {
+            continue;
+        }
+
+        let instruction = Instruction::decode(mmu.peek16(address));
+        pending.extend(successors(mmu, address, &instruction));
+    }
+
+    visited
+}
+
+/// Addresses execution can continue at after `instruction`. A `Call` continues
+/// both at its target and at the address it returns to. A skip continues after
+/// the next instruction, whose size is read from `mmu`.
+pub fn successors(mmu: &Mmu, address: u16, instruction: &Instruction) -> Vec<u16> {
+    match instruction {
+        Instruction::Ret | Instruction::JumpV0(_) | Instruction::Exit | Instruction::Invalid => {
+            vec![]
+        }
+        Instruction::Jmp(target) => vec![*target],
+        Instruction::Call(target) => vec![*target, address + 2],
+        _ if instruction.is_skip() => {
+            let next = address + 2;
+            vec![next, next + Instruction::decode(mmu.peek16(next)).size()]
+        }
+        _ => vec![address + instruction.size()],
+    }
+}
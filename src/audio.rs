@@ -0,0 +1,227 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use crate::error::Chip8Error;
+
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// Time the beeper takes to fade in or out, short enough to sound instant but
+/// long enough to avoid a click.
+const RAMP_SECONDS: f32 = 0.005;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    /// Value at `phase` in `0.0..1.0`.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+        }
+    }
+}
+
+/// What the beeper does while emulation runs faster or slower than real time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OffSpeedAudio {
+    Mute,
+    /// Play sped up or slowed down, changing the pitch.
+    Pitch,
+}
+
+impl OffSpeedAudio {
+    pub fn from_name(name: &str) -> Option<OffSpeedAudio> {
+        match name {
+            "mute" => Some(OffSpeedAudio::Mute),
+            "pitch" => Some(OffSpeedAudio::Pitch),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BeeperSettings {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// From 0.0 to 1.0.
+    pub volume: f32,
+}
+
+impl Default for BeeperSettings {
+    fn default() -> Self {
+        BeeperSettings {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Anything that produces audio one emulated frame at a time.
+pub trait SampleSource {
+    fn fill(&mut self, out: &mut [f32]);
+}
+
+/// Tone generator that sounds while the sound timer is running.
+pub struct Beeper {
+    settings: BeeperSettings,
+    sample_rate: u32,
+    on: bool,
+    pitch: f32,
+    /// XO-CHIP sample and the bits per second it plays at, replacing the
+    /// tone when set.
+    pattern: Option<([u8; 16], f32)>,
+    phase: f32,
+    /// Envelope that ramps towards 1.0 while on and 0.0 while off.
+    level: f32,
+}
+
+impl Beeper {
+    pub fn new(settings: BeeperSettings, sample_rate: u32) -> Beeper {
+        Beeper {
+            settings,
+            sample_rate,
+            on: false,
+            pitch: 1.0,
+            pattern: None,
+            phase: 0.0,
+            level: 0.0,
+        }
+    }
+
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// Multiplies the tone's frequency, for playing at other speeds.
+    pub fn set_pitch(&mut self, pitch: f32) {
+        self.pitch = pitch;
+    }
+
+    /// Plays an XO-CHIP 1-bit sample instead of the tone, at the rate its
+    /// pitch register gives: 4000 bits a second at 64, doubling every 48.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        self.pattern = pattern.map(|pattern| (pattern, rate));
+    }
+}
+
+impl SampleSource for Beeper {
+    fn fill(&mut self, out: &mut [f32]) {
+        // One phase cycle is a whole period of the tone, or all 128 bits of
+        // the pattern
+        let frequency = match self.pattern {
+            Some((_, rate)) => rate / 128.0,
+            None => self.settings.frequency,
+        };
+        let step = frequency * self.pitch / self.sample_rate as f32;
+        let ramp = 1.0 / (RAMP_SECONDS * self.sample_rate as f32);
+        let target = if self.on { 1.0 } else { 0.0 };
+
+        for sample in out.iter_mut() {
+            if self.level < target {
+                self.level = (self.level + ramp).min(target);
+            } else {
+                self.level = (self.level - ramp).max(target);
+            }
+
+            let value = match &self.pattern {
+                Some((pattern, _)) => {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                None => self.settings.waveform.sample(self.phase),
+            };
+            *sample = value * self.level * self.settings.volume;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/// Writes mono 16-bit PCM to a WAV file, for capturing audio without a
+/// sound card.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str) -> Result<WavWriter, Chip8Error> {
+        let file = File::create(path)
+            .map_err(|e| Chip8Error::new(&format!("Failed to create {}: {}", path, e)))?;
+        let mut writer = WavWriter {
+            file: BufWriter::new(file),
+            samples: 0,
+        };
+        // Sizes are filled in by `finish`
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), Chip8Error> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file
+                .write_all(&value.to_le_bytes())
+                .map_err(wav_error)?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Chip8Error> {
+        self.file.seek(SeekFrom::Start(0)).map_err(wav_error)?;
+        self.write_header()?;
+        self.file.flush().map_err(wav_error)
+    }
+
+    fn write_header(&mut self) -> Result<(), Chip8Error> {
+        let data_size = self.samples * 2;
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&1u16.to_le_bytes()); // mono
+        header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+        self.file.write_all(&header).map_err(wav_error)
+    }
+}
+
+fn wav_error(e: std::io::Error) -> Chip8Error {
+    Chip8Error::new(&format!("Failed to write wav: {}", e))
+}
@@ -0,0 +1,314 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use crate::error::Chip8Error;
+use crate::graphics::Graphics;
+use crate::postprocess::PostProcessor;
+use crate::scheduler::FRAME_RATE;
+
+/// A fresh file name next to the working directory, named after the ROM.
+pub fn capture_path(rom_path: &str, extension: &str) -> String {
+    let stem = std::path::Path::new(rom_path)
+        .file_stem()
+        .map_or("chip8".into(), |stem| stem.to_string_lossy());
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+
+    let mut path = format!("{}-{}.{}", stem, seconds, extension);
+    let mut attempt = 1;
+    while std::path::Path::new(&path).exists() {
+        attempt += 1;
+        path = format!("{}-{}-{}.{}", stem, seconds, attempt, extension);
+    }
+    path
+}
+
+/// `path` for the first recording and `path` with `-<number>` before its
+/// extension for later ones, so toggling recording again never overwrites an
+/// earlier take.
+pub fn numbered_path(path: &str, number: u32) -> String {
+    if number <= 1 {
+        return String::from(path);
+    }
+    let file = std::path::Path::new(path);
+    match (file.file_stem(), file.extension()) {
+        (Some(stem), Some(extension)) => file
+            .with_file_name(format!(
+                "{}-{}.{}",
+                stem.to_string_lossy(),
+                number,
+                extension.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}-{}", path, number),
+    }
+}
+
+/// An RGBA picture of the display, ready to be written out.
+#[derive(Clone, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Frame {
+    /// Captures the display as it is shown, enlarged `scale` times.
+    pub fn capture(graphics: &Graphics, postprocessor: &PostProcessor, scale: usize) -> Frame {
+        let mut rgba = Vec::new();
+        let frame = if postprocessor.effects().is_none() {
+            graphics.write_rgba(postprocessor.palette(), &mut rgba);
+            Frame {
+                width: graphics.width(),
+                height: graphics.height(),
+                rgba,
+            }
+        } else {
+            postprocessor.render(&mut rgba);
+            Frame {
+                width: postprocessor.output_width(),
+                height: postprocessor.output_height(),
+                rgba,
+            }
+        };
+
+        let scale = scale.max(1);
+        frame.resized(frame.width * scale, frame.height * scale)
+    }
+
+    /// Nearest neighbour resize, so integer scales stay sharp.
+    pub fn resized(&self, width: usize, height: usize) -> Frame {
+        if width == self.width && height == self.height {
+            return self.clone();
+        }
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let source_y = y * self.height / height;
+            for x in 0..width {
+                let source = (source_y * self.width + x * self.width / width) * 4;
+                rgba.extend_from_slice(&self.rgba[source..source + 4]);
+            }
+        }
+        Frame {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    pub fn save_png(&self, path: &str) -> Result<(), Chip8Error> {
+        let file = File::create(path)
+            .map_err(|e| Chip8Error::new(&format!("Failed to create {}: {}", path, e)))?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.rgba))
+            .map_err(|e| Chip8Error::new(&format!("Failed to write {}: {}", path, e)))
+    }
+
+    /// Indexes the frame against its own colours, if there are few enough of
+    /// them for a GIF palette.
+    fn indexed(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for pixel in self.rgba.chunks_exact(4) {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            let index = match palette.iter().position(|&c| c == color) {
+                Some(index) => index,
+                None if palette.len() < 256 => {
+                    palette.push(color);
+                    palette.len() - 1
+                }
+                None => return None,
+            };
+            pixels.push(index as u8);
+        }
+        Some((pixels, palette.concat()))
+    }
+}
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Png(PathBuf),
+}
+
+/// Records one frame per emulated frame, either as an animated GIF or as a
+/// directory of numbered PNGs. Every frame is resized to the first one's size
+/// so resolution switches don't break the recording.
+pub struct Recorder {
+    output: Output,
+    width: usize,
+    height: usize,
+    frames: u64,
+    /// A GIF frame waiting to learn how long it stays on screen.
+    pending: Option<(Frame, u64)>,
+}
+
+impl Recorder {
+    /// Paths ending in `.gif` record a GIF, anything else is a directory for
+    /// the PNG sequence.
+    pub fn create(path: &str, first: &Frame) -> Result<Recorder, Chip8Error> {
+        let error = |e: &dyn std::fmt::Display| {
+            Chip8Error::new(&format!("Failed to start recording {}: {}", path, e))
+        };
+
+        let output = if path.to_lowercase().ends_with(".gif") {
+            let file = File::create(path).map_err(|e| error(&e))?;
+            let mut encoder = gif::Encoder::new(
+                BufWriter::new(file),
+                first.width as u16,
+                first.height as u16,
+                &[],
+            )
+            .map_err(|e| error(&e))?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(|e| error(&e))?;
+            Output::Gif(encoder)
+        } else {
+            std::fs::create_dir_all(path).map_err(|e| error(&e))?;
+            Output::Png(PathBuf::from(path))
+        };
+
+        Ok(Recorder {
+            output,
+            width: first.width,
+            height: first.height,
+            frames: 0,
+            pending: None,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn record(&mut self, frame: &Frame) -> Result<(), Chip8Error> {
+        let frame = frame.resized(self.width, self.height);
+        let index = self.frames;
+        self.frames += 1;
+
+        match &self.output {
+            Output::Png(directory) => {
+                let path = directory.join(format!("frame_{:06}.png", index));
+                frame.save_png(&path.to_string_lossy())
+            }
+            Output::Gif(_) => {
+                // GIF delays are in hundredths of a second, so unchanged
+                // frames are merged and delays rounded from the frame count.
+                if matches!(&self.pending, Some((pending, _)) if *pending == frame) {
+                    return Ok(());
+                }
+                let previous = self.pending.replace((frame, index));
+                match previous {
+                    Some((previous, start)) => self.write_gif_frame(&previous, start, index),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn write_gif_frame(&mut self, frame: &Frame, start: u64, end: u64) -> Result<(), Chip8Error> {
+        let Output::Gif(encoder) = &mut self.output else {
+            return Ok(());
+        };
+
+        let centiseconds = |frame: u64| (frame * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+        let (width, height) = (frame.width as u16, frame.height as u16);
+        let mut gif_frame = match frame.indexed() {
+            Some((pixels, palette)) => {
+                gif::Frame::from_palette_pixels(width, height, pixels, palette, None)
+            }
+            None => gif::Frame::from_rgba_speed(width, height, &mut frame.rgba.clone(), 10),
+        };
+        gif_frame.delay = (centiseconds(end) - centiseconds(start)).max(1) as u16;
+
+        encoder
+            .write_frame(&gif_frame)
+            .map_err(|e| Chip8Error::new(&format!("Failed to write GIF frame: {}", e)))
+    }
+
+    /// Writes out anything still buffered and closes the recording.
+    pub fn finish(mut self) -> Result<(), Chip8Error> {
+        if let Some((frame, start)) = self.pending.take() {
+            self.write_gif_frame(&frame, start, self.frames)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(value: u8) -> Frame {
+        Frame {
+            width: 2,
+            height: 2,
+            rgba: [value, value, value, 255].repeat(4),
+        }
+    }
+
+    fn scratch_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chip8-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn gif_merges_unchanged_frames() {
+        let path = scratch_path("merge.gif");
+        let path_text = path.to_string_lossy().into_owned();
+        let mut recorder = Recorder::create(&path_text, &solid(0)).unwrap();
+        for frame in [0, 0, 0, 255, 255, 0] {
+            recorder.record(&solid(frame)).unwrap();
+        }
+        assert_eq!(recorder.frames(), 6);
+        recorder.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        std::fs::remove_file(&path).unwrap();
+        // 3, 2 and 1 frames at 60 Hz
+        assert_eq!(delays, vec![5, 3, 2]);
+    }
+
+    #[test]
+    fn png_sequence_is_numbered() {
+        let path = scratch_path("sequence");
+        let mut recorder = Recorder::create(&path.to_string_lossy(), &solid(0)).unwrap();
+        for frame in [0, 0, 255] {
+            recorder.record(&solid(frame)).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mut names: Vec<_> = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        std::fs::remove_dir_all(&path).unwrap();
+        assert_eq!(
+            names,
+            vec!["frame_000000.png", "frame_000001.png", "frame_000002.png"]
+        );
+    }
+
+    #[test]
+    fn later_recordings_are_numbered() {
+        assert_eq!(numbered_path("out.gif", 1), "out.gif");
+        assert_eq!(numbered_path("out.gif", 2), "out-2.gif");
+        assert_eq!(numbered_path("shots/run.gif", 3), "shots/run-3.gif");
+        assert_eq!(numbered_path("frames", 2), "frames-2");
+    }
+}
@@ -0,0 +1,468 @@
+/// Memory and I/O as seen by the CDP1802.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    /// `OUT n`, for n in 1..=7.
+    fn output(&mut self, port: u8, value: u8);
+    /// `INP n`, for n in 1..=7.
+    fn input(&mut self, port: u8) -> u8;
+    /// State of external flag input EF1 to EF4, for n in 1..=4.
+    fn flag(&self, n: u8) -> bool;
+}
+
+/// The RCA CDP1802 COSMAC microprocessor.
+pub struct Cdp1802 {
+    registers: [u16; 16],
+    d: u8,
+    df: bool,
+    p: u8,
+    x: u8,
+    t: u8,
+    ie: bool,
+    q: bool,
+    idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cdp1802 {
+    /// State after a reset: P, X and R0 are zero and interrupts are enabled.
+    pub fn new() -> Cdp1802 {
+        Cdp1802 {
+            registers: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    pub fn reg(&self, n: u8) -> u16 {
+        self.registers[n as usize]
+    }
+
+    pub fn set_reg(&mut self, n: u8, value: u16) {
+        self.registers[n as usize] = value;
+    }
+
+    pub fn q(&self) -> bool {
+        self.q
+    }
+
+    /// Takes an interrupt if they are enabled, returning the cycles spent.
+    pub fn interrupt(&mut self) -> Option<u32> {
+        if !self.ie {
+            return None;
+        }
+        self.t = (self.x << 4) | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        Some(1)
+    }
+
+    /// One DMA out cycle: reads the byte at R0 for a peripheral and advances R0.
+    pub fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        self.idle = false;
+        let value = bus.read(self.registers[0]);
+        self.registers[0] = self.registers[0].wrapping_add(1);
+        value
+    }
+
+    /// Executes one instruction and returns the machine cycles it took.
+    pub fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(bus);
+        let n = opcode & 0xF;
+
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = bus.read(self.reg(n)),
+            0x1 => self.increment(n),
+            0x2 => self.decrement(n),
+            0x3 => {
+                let target = self.fetch(bus);
+                let taken = match n {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    0x4..=0x7 => bus.flag(n - 3),
+                    // SKP only skips the byte already fetched
+                    0x8 => false,
+                    0x9 => !self.q,
+                    0xA => self.d != 0,
+                    0xB => !self.df,
+                    _ => !bus.flag(n - 0xB),
+                };
+                if taken {
+                    let page = self.reg(self.p) & 0xFF00;
+                    self.set_reg(self.p, page | target as u16);
+                }
+            }
+            0x4 => {
+                self.d = bus.read(self.reg(n));
+                self.increment(n);
+            }
+            0x5 => bus.write(self.reg(n), self.d),
+            0x6 => match n {
+                0x0 => self.increment(self.x),
+                // 68 is only defined on later members of the family
+                0x8 => {}
+                0x1..=0x7 => {
+                    let value = bus.read(self.reg(self.x));
+                    bus.output(n, value);
+                    self.increment(self.x);
+                }
+                _ => {
+                    let value = bus.input(n - 8);
+                    bus.write(self.reg(self.x), value);
+                    self.d = value;
+                }
+            },
+            0x7 => self.execute_7(n, bus),
+            0x8 => self.d = self.reg(n) as u8,
+            0x9 => self.d = (self.reg(n) >> 8) as u8,
+            0xA => self.set_reg(n, (self.reg(n) & 0xFF00) | self.d as u16),
+            0xB => self.set_reg(n, (self.reg(n) & 0x00FF) | ((self.d as u16) << 8)),
+            0xC => return self.execute_long(n, bus),
+            0xD => self.p = n,
+            0xE => self.x = n,
+            _ => self.execute_f(n, bus),
+        }
+
+        2
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.reg(self.p));
+        self.increment(self.p);
+        value
+    }
+
+    fn increment(&mut self, n: u8) {
+        self.set_reg(n, self.reg(n).wrapping_add(1));
+    }
+
+    fn decrement(&mut self, n: u8) {
+        self.set_reg(n, self.reg(n).wrapping_sub(1));
+    }
+
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let result = a as u16 + b as u16 + carry as u16;
+        self.d = result as u8;
+        self.df = result > 0xFF;
+    }
+
+    /// `a - b`, with DF set when there was no borrow.
+    fn subtract(&mut self, a: u8, b: u8, borrow: bool) {
+        let result = a as i16 - b as i16 - borrow as i16;
+        self.d = result as u8;
+        self.df = result >= 0;
+    }
+
+    fn execute_7(&mut self, n: u8, bus: &mut impl Bus) {
+        match n {
+            0x0 | 0x1 => {
+                let value = bus.read(self.reg(self.x));
+                self.increment(self.x);
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0;
+            }
+            0x2 => {
+                self.d = bus.read(self.reg(self.x));
+                self.increment(self.x);
+            }
+            0x3 => {
+                bus.write(self.reg(self.x), self.d);
+                self.decrement(self.x);
+            }
+            0x4 => {
+                let value = bus.read(self.reg(self.x));
+                self.add(value, self.d, self.df);
+            }
+            0x5 => {
+                let value = bus.read(self.reg(self.x));
+                self.subtract(value, self.d, !self.df);
+            }
+            0x6 => {
+                let carry = self.df;
+                self.df = self.d & 1 != 0;
+                self.d = (self.d >> 1) | ((carry as u8) << 7);
+            }
+            0x7 => {
+                let value = bus.read(self.reg(self.x));
+                self.subtract(self.d, value, !self.df);
+            }
+            0x8 => bus.write(self.reg(self.x), self.t),
+            0x9 => {
+                self.t = (self.x << 4) | self.p;
+                bus.write(self.reg(2), self.t);
+                self.x = self.p;
+                self.decrement(2);
+            }
+            0xA => self.q = false,
+            0xB => self.q = true,
+            0xC => {
+                let value = self.fetch(bus);
+                self.add(value, self.d, self.df);
+            }
+            0xD => {
+                let value = self.fetch(bus);
+                self.subtract(value, self.d, !self.df);
+            }
+            0xE => {
+                let carry = self.df;
+                self.df = self.d & 0x80 != 0;
+                self.d = (self.d << 1) | carry as u8;
+            }
+            _ => {
+                let value = self.fetch(bus);
+                self.subtract(self.d, value, !self.df);
+            }
+        }
+    }
+
+    /// Long branches and skips, which take three machine cycles.
+    fn execute_long(&mut self, n: u8, bus: &mut impl Bus) -> u32 {
+        let condition = match n & 0x3 {
+            0x0 => true,
+            0x1 => self.q,
+            0x2 => self.d == 0,
+            _ => self.df,
+        };
+
+        match n {
+            // LBR, LBQ, LBZ, LBDF and their inverses
+            0x0..=0x3 | 0x8..=0xB => {
+                let taken = if n < 0x8 { condition } else { !condition };
+                // C8 is LSKP rather than the inverse of LBR
+                if n == 0x8 {
+                    self.skip();
+                } else if taken {
+                    let high = bus.read(self.reg(self.p)) as u16;
+                    let low = bus.read(self.reg(self.p).wrapping_add(1)) as u16;
+                    self.set_reg(self.p, (high << 8) | low);
+                } else {
+                    self.skip();
+                }
+            }
+            0x4 => {}
+            // LSNQ, LSNZ, LSNF
+            0x5..=0x7 => {
+                if !condition {
+                    self.skip();
+                }
+            }
+            0xC => {
+                if self.ie {
+                    self.skip();
+                }
+            }
+            // LSQ, LSZ, LSDF
+            _ => {
+                if condition {
+                    self.skip();
+                }
+            }
+        }
+
+        3
+    }
+
+    fn skip(&mut self) {
+        let pc = self.reg(self.p).wrapping_add(2);
+        self.set_reg(self.p, pc);
+    }
+
+    fn execute_f(&mut self, n: u8, bus: &mut impl Bus) {
+        let value = if n == 0x6 || n == 0xE {
+            0
+        } else if n < 0x8 {
+            bus.read(self.reg(self.x))
+        } else {
+            self.fetch(bus)
+        };
+
+        match n & 0x7 {
+            0x0 => self.d = value,
+            0x1 => self.d |= value,
+            0x2 => self.d &= value,
+            0x3 => self.d ^= value,
+            0x4 => self.add(value, self.d, false),
+            0x5 => self.subtract(value, self.d, false),
+            0x6 if n == 0x6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            0x6 => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            _ => self.subtract(self.d, value, false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bus, Cdp1802};
+
+    struct TestBus {
+        memory: [u8; 256],
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize % 256]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize % 256] = value;
+        }
+
+        fn output(&mut self, _port: u8, _value: u8) {}
+
+        fn input(&mut self, _port: u8) -> u8 {
+            0
+        }
+
+        fn flag(&self, _n: u8) -> bool {
+            false
+        }
+    }
+
+    /// Runs `steps` instructions of `program`, loaded at 0 and run with P = 0.
+    fn run(program: &[u8], steps: usize) -> (Cdp1802, TestBus) {
+        let mut bus = TestBus { memory: [0; 256] };
+        bus.memory[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::new();
+        for _ in 0..steps {
+            cpu.step(&mut bus);
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn short_branches() {
+        // LDI 0, BZ 10
+        let (cpu, _) = run(&[0xF8, 0x00, 0x32, 0x10], 2);
+        assert_eq!(cpu.reg(0), 0x10);
+        // LDI 1, BZ 10
+        let (cpu, _) = run(&[0xF8, 0x01, 0x32, 0x10], 2);
+        assert_eq!(cpu.reg(0), 0x04);
+        // LDI 1, BNZ 10
+        let (cpu, _) = run(&[0xF8, 0x01, 0x3A, 0x10], 2);
+        assert_eq!(cpu.reg(0), 0x10);
+        // SKP skips only its own operand
+        let (cpu, _) = run(&[0x38, 0x10], 1);
+        assert_eq!(cpu.reg(0), 0x02);
+    }
+
+    #[test]
+    fn long_branches() {
+        let mut bus = TestBus { memory: [0; 256] };
+        bus.memory[..3].copy_from_slice(&[0xC0, 0x12, 0x34]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.reg(0), 0x1234);
+
+        // LDI 1, LBZ 1234 falls through past the address
+        let (cpu, _) = run(&[0xF8, 0x01, 0xC2, 0x12, 0x34], 2);
+        assert_eq!(cpu.reg(0), 0x05);
+        // LDI 1, LBNZ 0080
+        let (cpu, _) = run(&[0xF8, 0x01, 0xCA, 0x00, 0x80], 2);
+        assert_eq!(cpu.reg(0), 0x80);
+    }
+
+    #[test]
+    fn long_skips() {
+        // LSKP
+        let (cpu, _) = run(&[0xC8], 1);
+        assert_eq!(cpu.reg(0), 0x03);
+        // LDI 0, LSZ
+        let (cpu, _) = run(&[0xF8, 0x00, 0xCE], 2);
+        assert_eq!(cpu.reg(0), 0x05);
+        // LDI 0, LSNZ
+        let (cpu, _) = run(&[0xF8, 0x00, 0xC6], 2);
+        assert_eq!(cpu.reg(0), 0x03);
+        // LSIE, with interrupts enabled after reset
+        let (cpu, _) = run(&[0xCC], 1);
+        assert_eq!(cpu.reg(0), 0x03);
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        // LDI F0, ADI 20
+        let (cpu, _) = run(&[0xF8, 0xF0, 0xFC, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        // ADCI 01 adds the carry back in
+        let (cpu, _) = run(&[0xF8, 0xF0, 0xFC, 0x20, 0x7C, 0x01], 3);
+        assert_eq!((cpu.d, cpu.df), (0x12, false));
+    }
+
+    #[test]
+    fn subtract_clears_df_on_borrow() {
+        // LDI 10, SMI 20
+        let (cpu, _) = run(&[0xF8, 0x10, 0xFF, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0xF0, false));
+        // LDI 30, SMI 20
+        let (cpu, _) = run(&[0xF8, 0x30, 0xFF, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        // LDI 30, SDI 20 subtracts D from the operand
+        let (cpu, _) = run(&[0xF8, 0x30, 0xFD, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0xF0, false));
+        // LDI 10, SMI 20, SMBI 00 takes the borrow
+        let (cpu, _) = run(&[0xF8, 0x10, 0xFF, 0x20, 0x7F, 0x00], 3);
+        assert_eq!((cpu.d, cpu.df), (0xEF, true));
+    }
+
+    #[test]
+    fn shift_right_into_df() {
+        // LDI 03, SHR
+        let (cpu, _) = run(&[0xF8, 0x03, 0xF6], 2);
+        assert_eq!((cpu.d, cpu.df), (0x01, true));
+        // LDI 03, SHR, RSHR rotates DF into the top bit
+        let (cpu, _) = run(&[0xF8, 0x03, 0xF6, 0x76], 3);
+        assert_eq!((cpu.d, cpu.df), (0x80, true));
+    }
+
+    #[test]
+    fn mark_and_return() {
+        // LDI 80, PLO 2, SEX 3, MARK, INC 2, SEX 2, RET
+        let (cpu, bus) = run(&[0xF8, 0x80, 0xA2, 0xE3, 0x79, 0x12, 0xE2, 0x70], 4);
+        assert_eq!(bus.memory[0x80], 0x30);
+        assert_eq!(cpu.reg(2), 0x7F);
+        assert_eq!((cpu.x, cpu.p), (0, 0));
+
+        let (cpu, _) = run(&[0xF8, 0x80, 0xA2, 0xE3, 0x79, 0x12, 0xE2, 0x70], 7);
+        assert_eq!((cpu.x, cpu.p), (3, 0));
+        assert_eq!(cpu.reg(2), 0x81);
+        assert!(cpu.ie);
+    }
+
+    #[test]
+    fn disable_interrupts() {
+        // LDI 90, PLO 5, SEX 5, DIS with 21 at 90
+        let mut program = [0; 0x91];
+        program[..5].copy_from_slice(&[0xF8, 0x90, 0xA5, 0xE5, 0x71]);
+        program[0x90] = 0x21;
+        let (cpu, _) = run(&program, 4);
+        assert_eq!((cpu.x, cpu.p), (2, 1));
+        assert_eq!(cpu.reg(5), 0x91);
+        assert!(!cpu.ie);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::analysis;
+use crate::instruction::Instruction;
+use crate::mmu::{Mmu, ADDRESS_MASK, MEMORY_SIZE, PROGRAM_START};
+use crate::symbols::SymbolMap;
+
+/// Records which addresses were executed and which way each skip instruction went.
+#[derive(Clone)]
+pub struct Coverage {
+    hits: Vec<u32>,
+    skipped: Vec<u32>,
+    not_skipped: Vec<u32>,
+}
+
+#[derive(Default)]
+struct LineCoverage {
+    hits: u32,
+    executed: bool,
+    // (skipped, not skipped) for every skip instruction on the line
+    branches: Vec<(u32, u32)>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            hits: vec![0; MEMORY_SIZE],
+            skipped: vec![0; MEMORY_SIZE],
+            not_skipped: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    /// Records the instruction at `pc` after the cpu has moved on to `next_pc`.
+    pub fn record(&mut self, pc: u16, instruction: &Instruction, next_pc: u16) {
+        let address = pc as usize;
+        self.hits[address] = self.hits[address].saturating_add(1);
+
+        if instruction.is_skip() {
+            if next_pc != (pc + 2) & ADDRESS_MASK {
+                self.skipped[address] = self.skipped[address].saturating_add(1);
+            } else {
+                self.not_skipped[address] = self.not_skipped[address].saturating_add(1);
+            }
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u32 {
+        self.hits[address as usize]
+    }
+
+    pub fn is_covered(&self, address: u16) -> bool {
+        self.hits(address) > 0
+    }
+
+    /// Every address that is statically reachable or was executed.
+    pub fn code_addresses(&self, mmu: &Mmu) -> Vec<u16> {
+        let mut addresses = analysis::reachable_code(mmu, PROGRAM_START);
+        for (address, &hits) in self.hits.iter().enumerate() {
+            if hits > 0 {
+                addresses.insert(address as u16);
+            }
+        }
+        addresses.into_iter().collect()
+    }
+
+    /// Exports the run as an lcov tracefile. Without symbols every instruction
+    /// address is reported as its own line of `rom_name`.
+    pub fn to_lcov(&self, mmu: &Mmu, rom_name: &str, symbols: Option<&SymbolMap>) -> String {
+        let mut files: BTreeMap<String, BTreeMap<u32, LineCoverage>> = BTreeMap::new();
+
+        for address in self.code_addresses(mmu) {
+            let (file, line) = match symbols.and_then(|symbols| symbols.lookup(address)) {
+                Some(source) => (source.file.clone(), source.line),
+                None => (String::from(rom_name), address as u32),
+            };
+
+            let index = address as usize;
+            let entry = files.entry(file).or_default().entry(line).or_default();
+            entry.hits = entry.hits.max(self.hits[index]);
+            entry.executed |= self.hits[index] > 0;
+
+            if Instruction::decode(mmu.peek16(address)).is_skip() {
+                entry
+                    .branches
+                    .push((self.skipped[index], self.not_skipped[index]));
+            }
+        }
+
+        let mut output = String::new();
+        for (file, lines) in files {
+            writeln!(output, "TN:").unwrap();
+            writeln!(output, "SF:{}", file).unwrap();
+
+            let mut branches_found = 0;
+            let mut branches_hit = 0;
+            for (line, coverage) in &lines {
+                for (block, &(skipped, not_skipped)) in coverage.branches.iter().enumerate() {
+                    for (branch, taken) in [skipped, not_skipped].into_iter().enumerate() {
+                        branches_found += 1;
+                        if taken > 0 {
+                            branches_hit += 1;
+                        }
+                        if coverage.executed {
+                            writeln!(output, "BRDA:{},{},{},{}", line, block, branch, taken)
+                                .unwrap();
+                        } else {
+                            writeln!(output, "BRDA:{},{},{},-", line, block, branch).unwrap();
+                        }
+                    }
+                }
+            }
+            writeln!(output, "BRF:{}", branches_found).unwrap();
+            writeln!(output, "BRH:{}", branches_hit).unwrap();
+
+            for (line, coverage) in &lines {
+                writeln!(output, "DA:{},{}", line, coverage.hits).unwrap();
+            }
+            writeln!(output, "LF:{}", lines.len()).unwrap();
+            writeln!(
+                output,
+                "LH:{}",
+                lines.values().filter(|coverage| coverage.executed).count()
+            )
+            .unwrap();
+            writeln!(output, "end_of_record").unwrap();
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `3000` skips over `6001` to a jump to itself.
+    fn rom() -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.load_rom(vec![0x30, 0x00, 0x60, 0x01, 0x12, 0x04]);
+        mmu
+    }
+
+    #[test]
+    fn records_hits_and_skip_outcomes() {
+        let mmu = rom();
+        let mut coverage = Coverage::new();
+        let skip = Instruction::decode(mmu.read16(0x200));
+        coverage.record(0x200, &skip, 0x204);
+        coverage.record(0x200, &skip, 0x202);
+        coverage.record(0x200, &skip, 0x204);
+
+        assert_eq!(coverage.hits(0x200), 3);
+        assert_eq!(coverage.skipped[0x200], 2);
+        assert_eq!(coverage.not_skipped[0x200], 1);
+        assert!(!coverage.is_covered(0x202));
+        assert_eq!(coverage.code_addresses(&mmu), vec![0x200, 0x202, 0x204]);
+    }
+
+    #[test]
+    fn lcov_per_address() {
+        let mmu = rom();
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, &Instruction::decode(mmu.read16(0x200)), 0x204);
+        coverage.record(0x204, &Instruction::decode(mmu.read16(0x204)), 0x204);
+
+        assert_eq!(
+            coverage.to_lcov(&mmu, "rom.ch8", None),
+            "TN:\n\
+             SF:rom.ch8\n\
+             BRDA:512,0,0,1\n\
+             BRDA:512,0,1,0\n\
+             BRF:2\n\
+             BRH:1\n\
+             DA:512,1\n\
+             DA:514,0\n\
+             DA:516,1\n\
+             LF:3\n\
+             LH:2\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn lcov_through_symbols() {
+        let mmu = rom();
+        let mut coverage = Coverage::new();
+        coverage.record(0x204, &Instruction::decode(mmu.read16(0x204)), 0x204);
+        let symbols =
+            SymbolMap::parse("0x200 game.8o:3\n0x202 game.8o:4\n0x204 game.8o:4").unwrap();
+
+        let lcov = coverage.to_lcov(&mmu, "rom.ch8", Some(&symbols));
+        assert!(lcov.starts_with("TN:\nSF:game.8o\n"));
+        assert!(lcov.contains("BRDA:3,0,0,-\nBRDA:3,0,1,-\n"));
+        assert!(lcov.contains("DA:3,0\nDA:4,1\nLF:2\nLH:1\n"));
+    }
+}
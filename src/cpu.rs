@@ -1,14 +1,37 @@
-use crate::graphics::Graphics;
+use crate::error::Chip8Error;
+use crate::graphics::{Graphics, Resolution};
+use crate::input::Input;
 use crate::instruction::Instruction;
-use crate::mmu::Mmu;
+use crate::mmu::{Mmu, ADDRESS_MASK, BIG_FONT_START};
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, SeededRandom};
+use crate::snapshot::StateReader;
 
 pub struct Cpu {
     registers: [u8; 16],
     pc: u16,
     index: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    quirks: Quirks,
+    /// Key seen by a pending Fx0A, which completes once it is released.
+    key_wait: Option<u8>,
+    random: Box<dyn RandomSource>,
+    /// SUPER-CHIP's RPL user flags, extended to 16 by XO-CHIP.
+    flags: [u8; 16],
+    /// XO-CHIP's 1-bit sample, set by F002. Unset until a ROM loads one.
+    audio_pattern: Option<[u8; 16]>,
+    /// XO-CHIP playback rate of `audio_pattern`, 64 being 4000 Hz.
+    pitch: u8,
+    /// Set once 00FD or an error stops the program.
+    halted: bool,
+    error: Option<Chip8Error>,
 }
 
-#[derive(Copy, Clone)]
+/// Pitch register value that plays an XO-CHIP pattern at 4000 bits a second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Register {
     V0,
     V1,
@@ -29,7 +52,7 @@ pub enum Register {
 }
 
 impl Register {
-    fn to_index(&self) -> usize {
+    pub fn to_index(self) -> usize {
         match self {
             Register::V0 => 0,
             Register::V1 => 1,
@@ -73,15 +96,181 @@ impl Register {
     }
 }
 
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "V{:X}", self.to_index())
+    }
+}
+
+impl Clone for Cpu {
+    fn clone(&self) -> Self {
+        Cpu {
+            registers: self.registers,
+            pc: self.pc,
+            index: self.index,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            quirks: self.quirks,
+            key_wait: self.key_wait,
+            random: self.random.duplicate(),
+            flags: self.flags,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            halted: self.halted,
+            error: self.error.clone(),
+        }
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Cpu {
+        Cpu::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Cpu {
         Cpu {
             registers: [0; 16],
             pc: 0x200,
             index: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            quirks,
+            key_wait: None,
+            random: Box::new(SeededRandom::new(rand::random())),
+            flags: [0; 16],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            halted: false,
+            error: None,
         }
     }
 
+    /// Makes CXNN produce the same numbers on every run with this seed.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.set_random(Box::new(SeededRandom::new(seed)));
+    }
+
+    /// Replaces the source CXNN draws from.
+    pub fn set_random(&mut self, random: Box<dyn RandomSource>) {
+        self.random = random;
+    }
+
+    /// Puts the registers, timers, pc and XO-CHIP sound back to power-on
+    /// values, keeping the quirks, user flags and random source.
+    pub fn reset(&mut self) {
+        self.registers = [0; 16];
+        self.pc = 0x200;
+        self.index = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.key_wait = None;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.halted = false;
+        self.error = None;
+    }
+
+    /// Appends the registers, timers, XO-CHIP state and random generator
+    /// state to `out`.
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.index.to_le_bytes());
+        out.extend_from_slice(&[
+            self.delay_timer,
+            self.sound_timer,
+            self.key_wait.unwrap_or(0xFF),
+        ]);
+        out.extend_from_slice(&self.flags);
+        out.extend_from_slice(&[self.audio_pattern.is_some() as u8, self.pitch]);
+        out.extend_from_slice(&self.audio_pattern.unwrap_or_default());
+        out.push(self.halted as u8);
+        let random = self.random.save_state();
+        out.extend_from_slice(&(random.len() as u16).to_le_bytes());
+        out.extend_from_slice(&random);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.registers.copy_from_slice(reader.bytes(16)?);
+        self.pc = reader.u16()?;
+        self.index = reader.u16()?;
+        self.delay_timer = reader.u8()?;
+        self.sound_timer = reader.u8()?;
+        self.key_wait = Some(reader.u8()?).filter(|&key| key < 16);
+        self.flags.copy_from_slice(reader.bytes(16)?);
+        let has_pattern = reader.u8()? != 0;
+        self.pitch = reader.u8()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.bytes(16)?);
+        self.audio_pattern = has_pattern.then_some(pattern);
+        self.halted = reader.u8()? != 0;
+        self.error = None;
+        let length = reader.u16()? as usize;
+        self.random.load_state(reader.bytes(length)?);
+        Ok(())
+    }
+
+    /// Takes the reason the program stopped, if any.
+    pub fn take_error(&mut self) -> Option<Chip8Error> {
+        self.error.take()
+    }
+
+    /// Whether 00FD or an error has stopped the program.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The XO-CHIP sample loaded by F002, if any.
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Takes the problem the random source last ran into, if any.
+    pub fn take_random_error(&mut self) -> Option<Chip8Error> {
+        self.random.take_error()
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Key a pending Fx0A saw pressed and is waiting to be released.
+    pub fn key_wait(&self) -> Option<u8> {
+        self.key_wait
+    }
+
+    /// Counts both timers down by one, called once per 60 Hz frame.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
     pub fn reg(&self, register: Register) -> u8 {
         self.registers[register.to_index()]
     }
@@ -90,112 +279,450 @@ impl Cpu {
         self.registers[register.to_index()] = value;
     }
 
-    pub fn step(&mut self, mmu: &mut Mmu, graphics: &mut Graphics) {
-        let instruction_value = mmu.read16(self.pc);
-        self.pc += 2;
-        match Instruction::decode(instruction_value) {
+    /// Executes one instruction and returns it. Once halted the instruction
+    /// at pc is returned without running.
+    pub fn step(&mut self, mmu: &mut Mmu, graphics: &mut Graphics, input: &Input) -> Instruction {
+        if self.halted {
+            return Instruction::decode(mmu.peek16(self.pc));
+        }
+        let instruction_value = mmu.fetch16(self.pc);
+        self.advance(2);
+        let instruction = Instruction::decode(instruction_value);
+        match instruction {
             Instruction::Cls => graphics.clear(),
-            Instruction::Ret => self.pc = mmu.pop_stack(),
+            Instruction::Ret => match mmu.pop_stack() {
+                Ok(addr) => self.pc = addr,
+                Err(e) => self.fail(e),
+            },
             Instruction::Jmp(addr) => self.pc = addr,
-            Instruction::Call(addr) => {
-                mmu.push_stack(self.pc);
-                self.pc = addr;
-            }
+            Instruction::Call(addr) => match mmu.push_stack(self.pc) {
+                Ok(()) => self.pc = addr,
+                Err(e) => self.fail(e),
+            },
             Instruction::SkipInstructionEqual(register, constant) => {
                 if self.reg(register) == constant {
-                    self.pc += 2;
+                    self.skip(mmu);
                 }
             }
             Instruction::SkipInstructionNotEqual(register, constant) => {
                 if self.reg(register) != constant {
-                    self.pc += 2;
+                    self.skip(mmu);
                 }
             }
             Instruction::LoadConstant(register, constant) => self.set_reg(register, constant),
             Instruction::Add(register, constant) => {
-                self.set_reg(register, self.reg(register) + constant);
+                self.set_reg(register, self.reg(register).wrapping_add(constant));
             }
             Instruction::LoadRegister(x, y) => self.set_reg(x, self.reg(y)),
-            Instruction::OrRegister(x, y) => self.set_reg(x, self.reg(x) | self.reg(y)),
-            Instruction::AndRegister(x, y) => self.set_reg(x, self.reg(x) & self.reg(y)),
-            Instruction::XorRegister(x, y) => self.set_reg(x, self.reg(x) ^ self.reg(y)),
+            Instruction::OrRegister(x, y) => {
+                self.set_reg(x, self.reg(x) | self.reg(y));
+                self.reset_vf();
+            }
+            Instruction::AndRegister(x, y) => {
+                self.set_reg(x, self.reg(x) & self.reg(y));
+                self.reset_vf();
+            }
+            Instruction::XorRegister(x, y) => {
+                self.set_reg(x, self.reg(x) ^ self.reg(y));
+                self.reset_vf();
+            }
             Instruction::AddRegister(x, y) => {
-                let x_value = self.reg(x);
-                let y_value = self.reg(y);
-                self.set_reg(
-                    Register::VF,
-                    if x_value as u16 + y_value as u16 > 0xFF {
-                        1
-                    } else {
-                        0
-                    },
-                );
-                self.set_reg(x, x_value + y_value);
+                let (value, carry) = self.reg(x).overflowing_add(self.reg(y));
+                self.set_reg(x, value);
+                self.set_reg(Register::VF, carry as u8);
             }
             Instruction::SubRegister(x, y) => {
-                let x_value = self.reg(x);
-                let y_value = self.reg(y);
-                self.set_reg(Register::VF, if x_value > y_value { 1 } else { 0 });
-                self.set_reg(x, x_value - y_value);
+                let (value, borrow) = self.reg(x).overflowing_sub(self.reg(y));
+                self.set_reg(x, value);
+                self.set_reg(Register::VF, !borrow as u8);
             }
-            Instruction::Shr(x) => {
-                let x_value = self.reg(x);
+            Instruction::Shr(x, y) => {
+                let value = self.reg(if self.quirks.shift_in_place { x } else { y });
 
-                self.set_reg(Register::VF, x_value & 0x01);
-                self.set_reg(x, x_value >> 1);
+                self.set_reg(x, value >> 1);
+                self.set_reg(Register::VF, value & 0x01);
             }
             Instruction::Subn(x, y) => {
-                let x_value = self.reg(x);
-                let y_value = self.reg(y);
-                self.set_reg(Register::VF, if y_value > x_value { 1 } else { 0 });
-                self.set_reg(x, y_value - x_value);
+                let (value, borrow) = self.reg(y).overflowing_sub(self.reg(x));
+                self.set_reg(x, value);
+                self.set_reg(Register::VF, !borrow as u8);
             }
-            Instruction::Shl(x) => {
-                let x_value = self.reg(x);
+            Instruction::Shl(x, y) => {
+                let value = self.reg(if self.quirks.shift_in_place { x } else { y });
 
-                self.set_reg(Register::VF, x_value & 0x80);
-                self.set_reg(x, x_value << 1);
+                self.set_reg(x, value << 1);
+                self.set_reg(Register::VF, value >> 7);
             }
             Instruction::SkipInstructionRegisterEqual(x, y) => {
                 if self.reg(x) == self.reg(y) {
-                    self.pc += 2;
+                    self.skip(mmu);
                 }
             }
             Instruction::SkipInstructionRegisterNotEqual(x, y) => {
                 if self.reg(x) != self.reg(y) {
-                    self.pc += 2;
+                    self.skip(mmu);
                 }
             }
             Instruction::LoadIndex(constant) => self.index = constant,
-            Instruction::JumpV0(constant) => self.pc = self.reg(Register::V0) as u16 + constant,
+            Instruction::JumpV0(constant) => {
+                let register = if self.quirks.jump_uses_vx {
+                    Register::from_index((constant >> 8) as u8)
+                } else {
+                    Register::V0
+                };
+                self.pc = (self.reg(register) as u16 + constant) & ADDRESS_MASK;
+            }
             Instruction::Random(x, mask) => {
-                self.set_reg(x, rand::random::<u8>() & mask);
+                let value = self.random.next_byte();
+                self.set_reg(x, value & mask);
             }
             Instruction::Draw(x, y, num_bytes) => {
                 let x_coord = self.reg(x);
                 let y_coord = self.reg(y);
-                if graphics.draw(
+                let collided = graphics.draw(
                     self.index as usize,
                     num_bytes as usize,
                     x_coord as usize,
                     y_coord as usize,
                     mmu,
-                ) {
-                    self.set_reg(Register::VF, 1);
+                    self.quirks.clip_sprites,
+                );
+                self.set_reg(Register::VF, collided as u8);
+            }
+            Instruction::SkipIfPressed(x) => {
+                if input.key_pressed(self.reg(x) & 0xF) {
+                    self.skip(mmu);
+                }
+            }
+            Instruction::SkipIfNotPressed(x) => {
+                if !input.key_pressed(self.reg(x) & 0xF) {
+                    self.skip(mmu);
+                }
+            }
+            Instruction::LoadDelayTimer(x) => self.set_reg(x, self.delay_timer),
+            Instruction::WaitForKeyPress(x) => match self.key_wait {
+                Some(key) if !input.key_pressed(key) => {
+                    self.set_reg(x, key);
+                    self.key_wait = None;
+                }
+                _ => {
+                    if self.key_wait.is_none() {
+                        self.key_wait = (0..16).find(|&key| input.key_pressed(key));
+                    }
+                    self.rewind();
                 }
+            },
+            Instruction::StoreDelayTimer(x) => self.delay_timer = self.reg(x),
+            Instruction::StoreSoundTimer(x) => self.sound_timer = self.reg(x),
+            Instruction::AddIndex(x) => self.index = self.index.wrapping_add(self.reg(x) as u16),
+            Instruction::LoadSpriteIndex(x) => self.index = (self.reg(x) & 0xF) as u16 * 5,
+            Instruction::StoreBcd(x) => {
+                let value = self.reg(x);
+                mmu.write8(self.index, value / 100);
+                mmu.write8(self.index.wrapping_add(1), value / 10 % 10);
+                mmu.write8(self.index.wrapping_add(2), value % 10);
             }
-            Instruction::SkipIfPressed(_) => todo!(),
-            Instruction::SkipIfNotPressed(_) => todo!(),
-            Instruction::LoadDelayTimer(_) => todo!(),
-            Instruction::WaitForKeyPress(_) => todo!(),
-            Instruction::StoreDelayTimer(_) => todo!(),
-            Instruction::StoreSoundTimer(_) => todo!(),
-            Instruction::AddIndex(_) => todo!(),
-            Instruction::LoadSpriteIndex(_) => todo!(),
-            Instruction::StoreBcd(_) => todo!(),
-            Instruction::StoreRegisters(_) => todo!(),
-            Instruction::LoadRegisters(_) => todo!(),
-            Instruction::Invalid => todo!(),
+            Instruction::StoreRegisters(last) => {
+                for i in 0..=last.to_index() {
+                    mmu.write8(self.index.wrapping_add(i as u16), self.registers[i]);
+                }
+                if self.quirks.memory_increments_index {
+                    self.index = self.index.wrapping_add(last.to_index() as u16 + 1);
+                }
+            }
+            Instruction::LoadRegisters(last) => {
+                for i in 0..=last.to_index() {
+                    self.registers[i] = mmu.read8(self.index.wrapping_add(i as u16));
+                }
+                if self.quirks.memory_increments_index {
+                    self.index = self.index.wrapping_add(last.to_index() as u16 + 1);
+                }
+            }
+            Instruction::Exit => {
+                self.rewind();
+                self.halted = true;
+            }
+            Instruction::ScrollDown(lines) => graphics.scroll_down(lines as usize),
+            Instruction::ScrollRight => graphics.scroll_right(),
+            Instruction::ScrollLeft => graphics.scroll_left(),
+            Instruction::LowRes => graphics.set_resolution(Resolution::LOW),
+            Instruction::HighRes => graphics.set_resolution(Resolution::HIGH),
+            Instruction::ScrollUp(lines) => graphics.scroll_up(lines as usize),
+            Instruction::SelectPlanes(mask) => graphics.select_planes(mask),
+            Instruction::LoadBigSpriteIndex(x) => {
+                self.index = BIG_FONT_START + (self.reg(x) & 0xF) as u16 * 10;
+            }
+            Instruction::StoreFlags(last) => {
+                let count = last.to_index() + 1;
+                self.flags[..count].copy_from_slice(&self.registers[..count]);
+            }
+            Instruction::LoadFlags(last) => {
+                let count = last.to_index() + 1;
+                self.registers[..count].copy_from_slice(&self.flags[..count]);
+            }
+            Instruction::StoreRegisterRange(x, y) => {
+                for (offset, register) in register_range(x, y).enumerate() {
+                    mmu.write8(
+                        self.index.wrapping_add(offset as u16),
+                        self.registers[register],
+                    );
+                }
+            }
+            Instruction::LoadRegisterRange(x, y) => {
+                for (offset, register) in register_range(x, y).enumerate() {
+                    self.registers[register] = mmu.read8(self.index.wrapping_add(offset as u16));
+                }
+            }
+            Instruction::LongIndex => {
+                self.index = mmu.read16(self.pc);
+                self.advance(2);
+            }
+            Instruction::LoadAudio => {
+                let mut pattern = [0; 16];
+                for (i, byte) in pattern.iter_mut().enumerate() {
+                    *byte = mmu.read8(self.index.wrapping_add(i as u16));
+                }
+                self.audio_pattern = Some(pattern);
+            }
+            Instruction::SetPitch(x) => self.pitch = self.reg(x),
+            Instruction::Invalid => self.fail(Chip8Error::new(&format!(
+                "Invalid instruction {:04X}",
+                instruction_value
+            ))),
         }
+        instruction
+    }
+
+    /// Moves pc forward, wrapping at the end of memory.
+    fn advance(&mut self, bytes: u16) {
+        self.pc = self.pc.wrapping_add(bytes) & ADDRESS_MASK;
+    }
+
+    /// Points pc back at the instruction just fetched.
+    fn rewind(&mut self) {
+        self.pc = self.pc.wrapping_sub(2) & ADDRESS_MASK;
+    }
+
+    /// Steps over the next instruction, which is four bytes for XO-CHIP's
+    /// F000 NNNN.
+    fn skip(&mut self, mmu: &Mmu) {
+        self.advance(Instruction::decode(mmu.peek16(self.pc)).size());
+    }
+
+    /// Halts at the instruction just fetched, keeping `error` for the caller.
+    fn fail(&mut self, error: Chip8Error) {
+        self.rewind();
+        self.halted = true;
+        self.error = Some(Chip8Error::new(&format!(
+            "{} at {:03X}, stopping",
+            error, self.pc
+        )));
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.set_reg(Register::VF, 0);
+        }
+    }
+}
+
+/// Register indices from `x` to `y` inclusive, counting down when `y` is
+/// lower, as 5XY2 and 5XY3 walk them.
+fn register_range(x: Register, y: Register) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x.to_index(), y.to_index());
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cpu, Register};
+    use crate::graphics::Graphics;
+    use crate::input::Input;
+    use crate::mmu::{Mmu, BIG_FONT_START};
+
+    struct Chip8 {
+        cpu: Cpu,
+        mmu: Mmu,
+        graphics: Graphics,
+        input: Input,
+    }
+
+    impl Chip8 {
+        fn new(rom: &[u8]) -> Chip8 {
+            let mut mmu = Mmu::new();
+            mmu.load_rom(rom.to_vec());
+            Chip8 {
+                cpu: Cpu::new(),
+                mmu,
+                graphics: Graphics::new(),
+                input: Input::new(),
+            }
+        }
+
+        fn run(&mut self, steps: usize) {
+            for _ in 0..steps {
+                self.cpu
+                    .step(&mut self.mmu, &mut self.graphics, &self.input);
+            }
+        }
+    }
+
+    #[test]
+    fn exit_halts_in_place() {
+        let mut chip8 = Chip8::new(&[0x00, 0xFD, 0x60, 0x01]);
+        chip8.run(2);
+        assert!(chip8.cpu.is_halted());
+        assert_eq!(chip8.cpu.pc(), 0x200);
+        assert_eq!(chip8.cpu.reg(Register::V0), 0);
+        assert!(chip8.cpu.take_error().is_none());
+    }
+
+    #[test]
+    fn invalid_opcode_halts_with_error() {
+        let mut chip8 = Chip8::new(&[0xFF, 0xFF]);
+        chip8.run(1);
+        assert!(chip8.cpu.is_halted());
+        assert_eq!(chip8.cpu.pc(), 0x200);
+        assert_eq!(
+            chip8.cpu.take_error().unwrap().to_string(),
+            "Invalid instruction FFFF at 200, stopping"
+        );
+    }
+
+    #[test]
+    fn timers_and_bcd() {
+        // V0 = 234, delay = V0, V1 = delay, I = 0x300, BCD of V0, I += V0
+        let mut chip8 = Chip8::new(&[
+            0x60, 0xEA, 0xF0, 0x15, 0xF1, 0x07, 0xA3, 0x00, 0xF0, 0x33, 0xF0, 0x1E,
+        ]);
+        chip8.run(6);
+        assert_eq!(chip8.cpu.reg(Register::V1), 234);
+        assert_eq!(chip8.mmu.peek8(0x300), 2);
+        assert_eq!(chip8.mmu.peek8(0x301), 3);
+        assert_eq!(chip8.mmu.peek8(0x302), 4);
+        assert_eq!(chip8.cpu.index(), 0x300 + 234);
+        chip8.cpu.tick_timers();
+        assert_eq!(chip8.cpu.delay_timer(), 233);
+    }
+
+    #[test]
+    fn key_wait_completes_on_release() {
+        let mut chip8 = Chip8::new(&[0xF3, 0x0A, 0x60, 0x01]);
+        chip8.run(1);
+        assert_eq!(chip8.cpu.pc(), 0x200);
+        chip8.input.set_key_pressed(0xB, true);
+        chip8.run(1);
+        assert_eq!(chip8.cpu.key_wait(), Some(0xB));
+        chip8.input.set_key_pressed(0xB, false);
+        chip8.run(1);
+        assert_eq!(chip8.cpu.reg(Register::V3), 0xB);
+        assert_eq!(chip8.cpu.pc(), 0x202);
+    }
+
+    #[test]
+    fn skip_if_pressed() {
+        // V0 = 5, skip if key V0 pressed, V1 = 1, V2 = 1
+        let mut chip8 = Chip8::new(&[0x60, 0x05, 0xE0, 0x9E, 0x61, 0x01, 0x62, 0x01]);
+        chip8.input.set_key_pressed(5, true);
+        chip8.run(3);
+        assert_eq!(chip8.cpu.reg(Register::V1), 0);
+        assert_eq!(chip8.cpu.reg(Register::V2), 1);
+    }
+
+    #[test]
+    fn skip_steps_over_long_index() {
+        // skip if V0 == 0, I = 0x1234, V1 = 1
+        let mut chip8 = Chip8::new(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01]);
+        chip8.run(2);
+        assert_eq!(chip8.cpu.index(), 0);
+        assert_eq!(chip8.cpu.reg(Register::V1), 1);
+        assert_eq!(chip8.cpu.pc(), 0x208);
+    }
+
+    #[test]
+    fn big_font_digit() {
+        // V3 = 7, I = big digit for V3
+        let mut chip8 = Chip8::new(&[0x63, 0x07, 0xF3, 0x30]);
+        chip8.run(2);
+        assert_eq!(chip8.cpu.index(), BIG_FONT_START + 70);
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        // V0 = 1, V1 = 2, store V0-V1, clear both, load them back
+        let mut chip8 = Chip8::new(&[
+            0x60, 0x01, 0x61, 0x02, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85,
+        ]);
+        chip8.run(6);
+        assert_eq!(chip8.cpu.reg(Register::V0), 1);
+        assert_eq!(chip8.cpu.reg(Register::V1), 2);
+    }
+
+    #[test]
+    fn register_range_store_reverses() {
+        // V1 = 0x11, V2 = 0x22, I = 0x300, save V2-V1
+        let mut chip8 = Chip8::new(&[0x61, 0x11, 0x62, 0x22, 0xA3, 0x00, 0x52, 0x12]);
+        chip8.run(4);
+        assert_eq!(chip8.mmu.peek8(0x300), 0x22);
+        assert_eq!(chip8.mmu.peek8(0x301), 0x11);
+        assert_eq!(chip8.cpu.index(), 0x300);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        // I = 0x206, load pattern from the last instruction on, V0 = 100,
+        // pitch = V0
+        let mut chip8 = Chip8::new(&[0xA2, 0x06, 0xF0, 0x02, 0x60, 0x64, 0xF0, 0x3A]);
+        chip8.run(4);
+        let pattern = chip8.cpu.audio_pattern().unwrap();
+        assert_eq!(&pattern[..3], &[0xF0, 0x3A, 0x00]);
+        assert_eq!(chip8.cpu.pitch(), 100);
+    }
+
+    #[test]
+    fn addresses_past_memory_wrap() {
+        let roms: [&[u8]; 5] = [
+            // I = 0xFFFF, draw
+            &[0xF0, 0x00, 0xFF, 0xFF, 0xD0, 0x1F],
+            // I = 0xFFF, BCD of V0
+            &[0xAF, 0xFF, 0xF0, 0x33],
+            // I = 0xFFE, draw
+            &[0xAF, 0xFE, 0xD0, 0x1F],
+            // V0 = 0xFF, I = 0xFFF, I += V0, save V0-VF
+            &[0x60, 0xFF, 0xAF, 0xFF, 0xF0, 0x1E, 0xFF, 0x55],
+            // jump to the last byte of memory and run on
+            &[0x1F, 0xFF],
+        ];
+        for rom in roms {
+            let mut chip8 = Chip8::new(rom);
+            chip8.run(8);
+        }
+    }
+
+    #[test]
+    fn return_with_empty_stack_halts() {
+        let mut chip8 = Chip8::new(&[0x00, 0xEE]);
+        chip8.run(2);
+        assert!(chip8.cpu.is_halted());
+        assert_eq!(chip8.cpu.pc(), 0x200);
+        assert_eq!(
+            chip8.cpu.take_error().unwrap().to_string(),
+            "Return with an empty stack at 200, stopping"
+        );
+    }
+
+    #[test]
+    fn stack_overflow_halts() {
+        let mut chip8 = Chip8::new(&[0x22, 0x00]);
+        chip8.run(2000);
+        assert!(chip8.cpu.is_halted());
+        assert_eq!(
+            chip8.cpu.take_error().unwrap().to_string(),
+            "Stack overflow at 200, stopping"
+        );
     }
 }
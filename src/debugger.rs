@@ -0,0 +1,197 @@
+use crate::keymap::Keymap;
+use chip8::coverage::Coverage;
+use chip8::cpu::Cpu;
+use chip8::input::Input;
+use chip8::instruction::Instruction;
+use chip8::mmu::{Mmu, MEMORY_SIZE};
+
+const COVERED_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const UNCOVERED_COLOR: [f32; 4] = [0.9, 0.3, 0.3, 1.0];
+
+const HEATMAP_COLUMNS: usize = 64;
+const HEATMAP_CELL_SIZE: f32 = 6.0;
+
+/// The VIP keypad, row by row.
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+const KEYPAD_BUTTON_SIZE: f32 = 40.0;
+const PRESSED_COLOR: [f32; 4] = [0.2, 0.7, 0.2, 1.0];
+const TESTED_COLOR: [f32; 4] = [0.8, 0.7, 0.1, 1.0];
+const PRESSED_TESTED_COLOR: [f32; 4] = [0.5, 0.9, 0.1, 1.0];
+
+/// Disassembly of the reachable code, with never-executed instructions
+/// highlighted when `show_uncovered` is set.
+pub fn disassembly_window(
+    ui: &imgui::Ui,
+    cpu: &Cpu,
+    mmu: &Mmu,
+    coverage: &Coverage,
+    show_uncovered: &mut bool,
+) {
+    ui.window("Disassembly").build(|| {
+        ui.checkbox("Highlight uncovered", show_uncovered);
+        ui.separator();
+
+        for address in coverage.code_addresses(mmu) {
+            let value = mmu.peek16(address);
+            let marker = if address == cpu.pc() { ">" } else { " " };
+            let line = format!(
+                "{} {:03X}  {:04X}  {:<16} {}",
+                marker,
+                address,
+                value,
+                Instruction::decode(value).to_string(),
+                coverage.hits(address)
+            );
+
+            if *show_uncovered && !coverage.is_covered(address) {
+                ui.text_colored(UNCOVERED_COLOR, line);
+            } else {
+                ui.text_colored(COVERED_COLOR, line);
+            }
+        }
+    });
+}
+
+/// Memory laid out as a grid of `HEATMAP_COLUMNS` bytes per row, coloured by
+/// recent activity. Hovering a cell shows the last instruction that touched it.
+pub fn heatmap_window(ui: &imgui::Ui, mmu: &Mmu) {
+    ui.window("Memory Heatmap").build(|| {
+        ui.text("Red: write  Green: read  Blue: execute");
+
+        let rows = MEMORY_SIZE / HEATMAP_COLUMNS;
+        let origin = ui.cursor_screen_pos();
+        let heatmap = mmu.heatmap();
+        let draw_list = ui.get_window_draw_list();
+
+        for address in 0..MEMORY_SIZE {
+            let x = origin[0] + (address % HEATMAP_COLUMNS) as f32 * HEATMAP_CELL_SIZE;
+            let y = origin[1] + (address / HEATMAP_COLUMNS) as f32 * HEATMAP_CELL_SIZE;
+            draw_list
+                .add_rect(
+                    [x, y],
+                    [x + HEATMAP_CELL_SIZE - 1.0, y + HEATMAP_CELL_SIZE - 1.0],
+                    heatmap.color(address as u16),
+                )
+                .filled(true)
+                .build();
+        }
+
+        ui.dummy([
+            HEATMAP_COLUMNS as f32 * HEATMAP_CELL_SIZE,
+            rows as f32 * HEATMAP_CELL_SIZE,
+        ]);
+
+        if ui.is_item_hovered() {
+            let mouse = ui.io().mouse_pos;
+            let column = ((mouse[0] - origin[0]) / HEATMAP_CELL_SIZE) as usize;
+            let row = ((mouse[1] - origin[1]) / HEATMAP_CELL_SIZE) as usize;
+            let address = (row * HEATMAP_COLUMNS + column.min(HEATMAP_COLUMNS - 1)) as u16;
+
+            match heatmap.last_pc(address) {
+                Some(pc) => ui.tooltip_text(format!(
+                    "{:03X}: {:02X}  last pc {:03X}",
+                    address,
+                    mmu.peek8(address),
+                    pc
+                )),
+                None => ui.tooltip_text(format!(
+                    "{:03X}: {:02X}  untouched",
+                    address,
+                    mmu.peek8(address)
+                )),
+            }
+        }
+    });
+}
+
+/// A clickable VIP keypad showing which keys are down. The key the next
+/// instruction tests is highlighted. `clicked` remembers the key held with
+/// the mouse so it can be released without disturbing the keyboard. Returns
+/// the keys clicked or let go.
+pub fn keypad_window(
+    ui: &imgui::Ui,
+    cpu: Option<&Cpu>,
+    mmu: &Mmu,
+    input: &Input,
+    keymap: &Keymap,
+    clicked: &mut Option<u8>,
+) -> Vec<(u8, bool)> {
+    // Worked out after the window, so a click is released even when the
+    // window is collapsed or closed mid-press
+    let mut held = None;
+    let mut changes = vec![];
+    ui.window("Keypad").build(|| {
+        let (tested, waiting) = match cpu {
+            Some(cpu) => tested_key(cpu, mmu),
+            None => (None, false),
+        };
+
+        for row in KEYPAD_LAYOUT {
+            for (column, key) in row.into_iter().enumerate() {
+                if column > 0 {
+                    ui.same_line();
+                }
+
+                let color = match (input.key_pressed(key), tested == Some(key)) {
+                    (true, true) => Some(PRESSED_TESTED_COLOR),
+                    (true, false) => Some(PRESSED_COLOR),
+                    (false, true) => Some(TESTED_COLOR),
+                    (false, false) => None,
+                };
+                let _color =
+                    color.map(|color| ui.push_style_color(imgui::StyleColor::Button, color));
+
+                ui.button_with_size(
+                    format!("{:X}", key),
+                    [KEYPAD_BUTTON_SIZE, KEYPAD_BUTTON_SIZE],
+                );
+                if ui.is_item_active() {
+                    held = Some(key);
+                }
+                if ui.is_item_hovered() {
+                    let hosts: Vec<_> =
+                        keymap.host_keys(key).map(|host| host.to_string()).collect();
+                    ui.tooltip_text(match hosts.is_empty() {
+                        true => String::from("Unbound"),
+                        false => hosts.join(", "),
+                    });
+                }
+            }
+        }
+
+        ui.separator();
+        match (tested, waiting) {
+            (Some(key), true) => ui.text(format!("Fx0A waiting for {:X} to be released", key)),
+            (None, true) => ui.text("Fx0A waiting for any key"),
+            (Some(key), false) => ui.text(format!("Testing key {:X}", key)),
+            (None, false) => ui.text("No key test pending"),
+        }
+    });
+
+    if held != *clicked {
+        if let Some(key) = *clicked {
+            changes.push((key, keymap.is_held(key)));
+        }
+        if let Some(key) = held {
+            changes.push((key, true));
+        }
+        *clicked = held;
+    }
+    changes
+}
+
+/// The key the instruction at pc checks, and whether it is an Fx0A wait.
+fn tested_key(cpu: &Cpu, mmu: &Mmu) -> (Option<u8>, bool) {
+    match Instruction::decode(mmu.peek16(cpu.pc())) {
+        Instruction::SkipIfPressed(x) | Instruction::SkipIfNotPressed(x) => {
+            (Some(cpu.reg(x) & 0xF), false)
+        }
+        Instruction::WaitForKeyPress(_) => (cpu.key_wait(), true),
+        _ => (None, false),
+    }
+}
@@ -0,0 +1,71 @@
+use crate::analysis;
+use crate::instruction::Instruction;
+use crate::lint;
+use crate::mmu::{Mmu, PROGRAM_START};
+use crate::platform::Platform;
+use crate::quirks::{Quirks, QuirksPreset};
+
+/// The platform and quirks a ROM most likely expects, and why.
+pub struct Detection {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// From 0.0 (a guess) to 1.0 (certain).
+    pub confidence: f32,
+    pub reasons: Vec<String>,
+}
+
+/// Scans the reachable code of the loaded ROM for opcodes and patterns that
+/// only make sense on one platform.
+pub fn detect(mmu: &Mmu) -> Detection {
+    let mut platform = Platform::Chip8;
+    let mut reasons = vec![];
+    let mut only_big_sprites = true;
+
+    for address in analysis::reachable_code(mmu, PROGRAM_START) {
+        let instruction = Instruction::decode(mmu.peek16(address));
+        let required = instruction.platform();
+        if required == Platform::Chip8 {
+            continue;
+        }
+
+        if !matches!(instruction, Instruction::Draw(..)) {
+            only_big_sprites = false;
+        }
+        if required > platform {
+            reasons.push(format!(
+                "{} at {:03X} requires {}",
+                instruction, address, required
+            ));
+            platform = required;
+        }
+    }
+
+    let report = lint::lint(mmu);
+    let preset = match platform {
+        Platform::Chip8 => report.preset,
+        _ => platform.default_quirks(),
+    };
+    if platform == Platform::Chip8 && preset != QuirksPreset::Chip8 {
+        reasons.push(format!("quirk-sensitive code expects {} quirks", preset));
+    }
+
+    let confidence = match platform {
+        Platform::XoChip => 0.95,
+        // DXY0 draws nothing on CHIP-8, so it alone is weak evidence
+        Platform::SuperChip if only_big_sprites => 0.6,
+        Platform::SuperChip => 0.9,
+        Platform::Chip8 if preset != QuirksPreset::Chip8 => 0.5,
+        Platform::Chip8 => 0.7,
+    };
+
+    if reasons.is_empty() {
+        reasons.push(String::from("no extended opcodes found"));
+    }
+
+    Detection {
+        platform,
+        quirks: preset.quirks(),
+        confidence,
+        reasons,
+    }
+}
@@ -0,0 +1,481 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::options::Options;
+use chip8::capture;
+use chip8::coverage::Coverage;
+use chip8::cpu::Cpu;
+use chip8::error::Chip8Error;
+use chip8::graphics::Graphics;
+use chip8::input::Input;
+use chip8::machine::Machine;
+use chip8::mmu::Mmu;
+use chip8::movie::Movie;
+use chip8::postprocess::PostProcessor;
+use chip8::scheduler::{Pacer, SpeedControl};
+use chip8::snapshot::Rewind;
+use chip8::vip::Vip;
+
+/// Per-frame decay of the memory heatmap, roughly a second to fade out at 60 Hz.
+const HEATMAP_FADE: f32 = 0.95;
+
+/// Longest the emulation thread sleeps waiting for a command before checking
+/// whether a frame is due.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Requests from the frontend to the emulation thread.
+pub enum Command {
+    Key(u8, bool),
+    TogglePause,
+    Advance,
+    /// Runs a single instruction.
+    Step,
+    SetFastForward(bool),
+    CycleFastForward,
+    ToggleSlowMotion,
+    SetRewinding(bool),
+    Screenshot,
+    ToggleRecording,
+    /// Replaces the ROM with the one at the path, keeping the platform and
+    /// quirks.
+    Load(String),
+    /// Frames the frontend wants run, when it paces emulation itself.
+    RunFrames(u32),
+    Quit,
+}
+
+/// How the emulation thread decides when to run frames.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// By the wall clock.
+    RealTime,
+    /// Only when sent `Command::RunFrames`, for syncing to the audio device.
+    External,
+}
+
+/// What the frontend needs to draw a frame and the debugger panels, copied
+/// out of the emulation thread.
+pub struct Published {
+    /// The display to show. Only rows changed since the last one are dirty.
+    pub graphics: Graphics,
+    /// The post-processed picture, when effects are on and frames have run.
+    pub processed: Option<PostProcessor>,
+    /// Whether the beeper sounded in each frame run since the last one.
+    pub sounds: Vec<bool>,
+    /// Whether the beeper is sounding now.
+    pub sound: bool,
+    /// Whether this answers a `Command::RunFrames`.
+    pub paced: bool,
+    pub speed: f32,
+    pub paused: bool,
+    pub indicator: Option<String>,
+    pub input: Input,
+    pub cpu: Cpu,
+    pub mmu: Mmu,
+    pub coverage: Coverage,
+    /// Whether an emulated VIP runs instead of `cpu`.
+    pub vip: bool,
+    /// Frames and bytes of rewind history, when rewind is on.
+    pub rewind: Option<(u64, usize)>,
+    pub rewinding: bool,
+}
+
+/// Everything that runs a ROM, apart from presenting it. It can be driven a
+/// frame at a time, or moved onto its own thread with `spawn`.
+pub struct Emulator {
+    pub machine: Machine,
+    pub vip: Option<Vip>,
+    pub postprocessor: PostProcessor,
+    pub rewind: Option<Rewind>,
+    pub rewinding: bool,
+    /// Frames shown ahead of the machine to hide input lag.
+    pub run_ahead: u32,
+    pub speed: SpeedControl,
+    pub playback: Option<Movie>,
+    pub recording_movie: Option<Movie>,
+    /// Whether the played movie ended with the recorded state, once it has.
+    pub movie_verified: Option<bool>,
+    pub frames_run: u64,
+    pub recorder: Option<capture::Recorder>,
+    pub recording: bool,
+    /// Where recordings go, numbered after the first, instead of a
+    /// generated name.
+    pub record_path: Option<String>,
+    /// Recordings started so far.
+    pub recordings: u32,
+    pub capture_scale: usize,
+    pub rom_path: String,
+    /// Frames run since the last `publish`.
+    ran: u32,
+    /// Whether the last published display was run ahead of the machine.
+    ahead_shown: bool,
+}
+
+impl Emulator {
+    pub fn new(
+        options: &Options,
+        machine: Machine,
+        vip: Option<Vip>,
+        postprocessor: PostProcessor,
+    ) -> Emulator {
+        Emulator {
+            machine,
+            vip,
+            postprocessor,
+            rewind: None,
+            rewinding: false,
+            run_ahead: 0,
+            speed: SpeedControl::new(options.fast_forward.clone(), options.slow_motion),
+            playback: None,
+            recording_movie: None,
+            movie_verified: None,
+            frames_run: 0,
+            recorder: None,
+            recording: options.record_path.is_some(),
+            record_path: options.record_path.clone(),
+            recordings: 0,
+            capture_scale: options.capture_scale,
+            rom_path: options.rom_path.clone(),
+            ran: 0,
+            ahead_shown: false,
+        }
+    }
+
+    pub fn sound(&self) -> bool {
+        match &self.vip {
+            Some(vip) => vip.sound(),
+            None => self.machine.sound(),
+        }
+    }
+
+    /// Runs one frame, or steps back one while rewinding.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        // Movies can't follow the machine backwards, so rewind waits until
+        // they are done.
+        let rewound = self.rewinding
+            && self.playback.is_none()
+            && self.recording_movie.is_none()
+            && match &mut self.rewind {
+                Some(rewind) => rewind.step_back(&mut self.machine)?,
+                None => false,
+            };
+
+        if !rewound {
+            self.update_movies();
+            match &mut self.vip {
+                Some(vip) => vip.run_frame(self.machine.input()),
+                None => self.machine.run_frame(),
+            }
+            self.report_error();
+            self.frames_run += 1;
+            if self
+                .playback
+                .as_ref()
+                .is_some_and(|movie| movie.is_finished(self.frames_run))
+            {
+                let movie = self.playback.take().unwrap();
+                self.movie_verified = Some(check_movie(&movie, &self.machine.state_hash()));
+                println!("Movie finished, returning control");
+            }
+            if let Some(rewind) = &mut self.rewind {
+                rewind.record(&self.machine);
+            }
+        }
+
+        if let Some(vip) = &self.vip {
+            vip.render(self.machine.graphics_mut());
+        }
+        self.machine.mmu_mut().heatmap_mut().fade(HEATMAP_FADE);
+        if !self.postprocessor.effects().is_none() {
+            self.postprocessor.advance(self.machine.graphics());
+        }
+        self.ran += 1;
+        Ok(())
+    }
+
+    /// Feeds a playing movie's input to the keypad, then records the keypad,
+    /// so a replay can itself be re-recorded.
+    fn update_movies(&mut self) {
+        if let Some(movie) = &mut self.playback {
+            movie.apply_input(self.frames_run, self.machine.input_mut());
+        }
+        if let Some(movie) = &mut self.recording_movie {
+            movie.record_input(self.frames_run, self.machine.input());
+        }
+    }
+
+    /// Prints why the program stopped, or any other problem it ran into.
+    fn report_error(&mut self) {
+        if let Some(error) = self.machine.take_error() {
+            println!("{}", error);
+        }
+    }
+
+    /// Runs a single instruction of the CHIP-8 machine. Stepping between
+    /// frames would desync a movie from its input, so it is ignored while
+    /// one is playing or recording.
+    pub fn step_instruction(&mut self) {
+        let movie_active = self.playback.is_some() || self.recording_movie.is_some();
+        if self.vip.is_some() || self.machine.cpu().is_halted() || movie_active {
+            return;
+        }
+        self.machine.step();
+        self.report_error();
+    }
+
+    /// Adds the current frame to the recording, if one is running.
+    pub fn record_frame(&mut self) -> Result<(), Chip8Error> {
+        if !self.recording {
+            return Ok(());
+        }
+        let frame = capture::Frame::capture(
+            self.machine.graphics(),
+            &self.postprocessor,
+            self.capture_scale,
+        );
+        if self.recorder.is_none() {
+            self.recordings += 1;
+            let path = match &self.record_path {
+                Some(path) => capture::numbered_path(path, self.recordings),
+                None => capture::capture_path(&self.rom_path, "gif"),
+            };
+            self.recorder = Some(capture::Recorder::create(&path, &frame)?);
+            println!("Recording to {}", path);
+        }
+        match &mut self.recorder {
+            Some(recorder) => recorder.record(&frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops any recording and writes it out.
+    pub fn finish_recording(&mut self) -> Result<(), Chip8Error> {
+        match self.recorder.take() {
+            Some(recorder) => {
+                let frames = recorder.frames();
+                recorder.finish()?;
+                println!("Recorded {} frames", frames);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn screenshot(&self, path: &str) -> Result<(), Chip8Error> {
+        capture::Frame::capture(
+            self.machine.graphics(),
+            &self.postprocessor,
+            self.capture_scale,
+        )
+        .save_png(path)
+    }
+
+    /// Swaps in a new ROM and starts it from scratch.
+    pub fn load(&mut self, path: &str) -> Result<(), Chip8Error> {
+        if self.vip.is_some() {
+            return Err(Chip8Error::new("Can't load a ROM into the emulated VIP"));
+        }
+        if self.playback.is_some() || self.recording_movie.is_some() {
+            return Err(Chip8Error::new("Can't load a ROM during a movie"));
+        }
+        let rom = std::fs::read(path)
+            .map_err(|e| Chip8Error::new(&format!("Failed to read {}: {}", path, e)))?;
+
+        self.machine.load_rom(&rom)?;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        self.rom_path = String::from(path);
+        println!("Loaded ROM: {}", path);
+        Ok(())
+    }
+
+    /// Carries out a command. Pacing commands are left to the caller.
+    pub fn handle(&mut self, command: Command) {
+        match command {
+            Command::Key(key, pressed) => self.machine.set_key(key, pressed),
+            Command::TogglePause => self.speed.toggle_pause(),
+            Command::Advance => self.speed.advance(),
+            Command::Step => self.step_instruction(),
+            Command::SetFastForward(on) => self.speed.set_fast_forward(on),
+            Command::CycleFastForward => self.speed.cycle_multiplier(),
+            Command::ToggleSlowMotion => self.speed.toggle_slow_motion(),
+            Command::SetRewinding(on) => self.rewinding = on,
+            Command::Screenshot => {
+                let path = capture::capture_path(&self.rom_path, "png");
+                match self.screenshot(&path) {
+                    Ok(()) => println!("Saved screenshot {}", path),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            Command::ToggleRecording => {
+                self.recording = !self.recording;
+                if let Err(e) = self.finish_recording() {
+                    println!("{}", e);
+                }
+            }
+            Command::Load(path) => {
+                if let Err(e) = self.load(&path) {
+                    println!("{}", e);
+                }
+            }
+            Command::RunFrames(_) | Command::Quit => {}
+        }
+    }
+
+    /// Copies out what the frontend shows. With run-ahead the display comes
+    /// from a throwaway future, whose coverage, heatmap and effects history
+    /// are dropped before the present is put back.
+    pub fn publish(&mut self, sounds: Vec<bool>, paced: bool) -> Result<Published, Chip8Error> {
+        let effects = !self.postprocessor.effects().is_none();
+        let ahead = self.run_ahead > 0 && self.ran > 0 && self.vip.is_none() && !self.rewinding;
+
+        let mut graphics;
+        let mut processed = None;
+        if ahead {
+            graphics = self.machine.run_ahead(self.run_ahead)?;
+            // The frontend's picture no longer matches the present, so the
+            // whole of it is redrawn.
+            graphics.mark_all_dirty();
+            if effects {
+                let mut ahead_postprocessor = self.postprocessor.clone();
+                ahead_postprocessor.advance(&graphics);
+                processed = Some(ahead_postprocessor);
+            }
+        } else {
+            // Until frames run the last future stays up.
+            graphics = self.machine.graphics().clone();
+            if self.ahead_shown && self.ran > 0 {
+                graphics.mark_all_dirty();
+            }
+            if effects && self.ran > 0 {
+                processed = Some(self.postprocessor.clone());
+            }
+        }
+        self.machine.graphics_mut().clear_dirty();
+        self.ahead_shown = ahead || (self.ahead_shown && self.ran == 0);
+        self.ran = 0;
+
+        Ok(Published {
+            graphics,
+            processed,
+            sounds,
+            sound: self.sound(),
+            paced,
+            speed: self.speed.speed(),
+            paused: self.speed.is_paused(),
+            indicator: self.speed.indicator(),
+            input: *self.machine.input(),
+            cpu: self.machine.cpu().clone(),
+            mmu: self.machine.mmu().clone(),
+            coverage: self.machine.coverage().clone(),
+            vip: self.vip.is_some(),
+            rewind: self
+                .rewind
+                .as_ref()
+                .map(|rewind| (rewind.frames(), rewind.memory_used())),
+            rewinding: self.rewinding,
+        })
+    }
+
+    /// Moves the emulator onto its own thread. It takes commands until sent
+    /// `Command::Quit` or the sender is dropped, then hands itself back.
+    #[allow(clippy::type_complexity)]
+    pub fn spawn(
+        self,
+        pacing: Pacing,
+    ) -> (
+        Sender<Command>,
+        Receiver<Published>,
+        JoinHandle<Result<Emulator, Chip8Error>>,
+    ) {
+        let (command_sender, commands) = mpsc::channel();
+        let (frame_sender, frames) = mpsc::channel();
+        let thread = std::thread::spawn(move || self.run(pacing, commands, frame_sender));
+        (command_sender, frames, thread)
+    }
+
+    fn run(
+        mut self,
+        pacing: Pacing,
+        commands: Receiver<Command>,
+        frames: Sender<Published>,
+    ) -> Result<Emulator, Chip8Error> {
+        let mut pacer = Pacer::new();
+        if frames.send(self.publish(vec![], false)?).is_err() {
+            return Ok(self);
+        }
+
+        loop {
+            let first = match pacing {
+                Pacing::RealTime => match commands.recv_timeout(POLL_INTERVAL) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(self),
+                },
+                Pacing::External => match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return Ok(self),
+                },
+            };
+
+            let changed = first.is_some();
+            let mut due = 0;
+            let mut paced = false;
+            for command in first.into_iter().chain(commands.try_iter()) {
+                match command {
+                    Command::Quit => return Ok(self),
+                    Command::RunFrames(frames) => {
+                        due += frames;
+                        paced = true;
+                    }
+                    Command::TogglePause => {
+                        self.speed.toggle_pause();
+                        pacer.reset();
+                    }
+                    command => self.handle(command),
+                }
+            }
+            if pacing == Pacing::RealTime {
+                pacer.set_speed(self.speed.speed());
+                due = pacer.frames_due();
+            }
+
+            let mut sounds = vec![];
+            for _ in 0..self.speed.frames(due) {
+                self.run_frame()?;
+                if let Err(e) = self.record_frame() {
+                    println!("{}", e);
+                    self.recording = false;
+                }
+                sounds.push(self.sound());
+            }
+
+            if changed || !sounds.is_empty() {
+                let published = self.publish(sounds, paced)?;
+                if frames.send(published).is_err() {
+                    return Ok(self);
+                }
+            }
+        }
+    }
+}
+
+/// Compares the final state of a replay with the recording and reports it.
+pub fn check_movie(movie: &Movie, hash: &str) -> bool {
+    match &movie.hash {
+        Some(expected) if expected == hash => {
+            println!("Movie verified: {}", hash);
+            true
+        }
+        Some(expected) => {
+            println!("Movie diverged: expected {}, got {}", expected, hash);
+            false
+        }
+        None => {
+            println!("Movie has no recorded hash, final state {}", hash);
+            true
+        }
+    }
+}
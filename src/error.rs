@@ -0,0 +1,20 @@
+use std::{error::Error, str::FromStr};
+
+#[derive(Clone, Debug)]
+pub struct Chip8Error {
+    message: String,
+}
+impl Chip8Error {
+    pub fn new(message: &str) -> Chip8Error {
+        Chip8Error {
+            message: String::from_str(message).unwrap(),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+impl std::fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
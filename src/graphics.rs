@@ -1,72 +1,448 @@
+use crate::error::Chip8Error;
 use crate::mmu::Mmu;
+use crate::postprocess::Palette;
+use crate::snapshot::StateReader;
 
-pub const DISPLAY_WIDTH: usize = 64;
-pub const DISPLAY_HEIGHT: usize = 32;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Resolution {
+    /// The original CHIP-8 display, and SUPER-CHIP and XO-CHIP low resolution.
+    pub const LOW: Resolution = Resolution {
+        width: 64,
+        height: 32,
+    };
+    /// Two-page CHIP-8 display.
+    pub const TALL: Resolution = Resolution {
+        width: 64,
+        height: 64,
+    };
+    /// SUPER-CHIP and XO-CHIP high resolution.
+    pub const HIGH: Resolution = Resolution {
+        width: 128,
+        height: 64,
+    };
+    /// MEGA-CHIP display.
+    pub const MEGA: Resolution = Resolution {
+        width: 256,
+        height: 192,
+    };
+    /// Every resolution the display supports.
+    pub const ALL: [Resolution; 4] = [
+        Resolution::LOW,
+        Resolution::TALL,
+        Resolution::HIGH,
+        Resolution::MEGA,
+    ];
+}
 
+/// Bitplanes of one bit per pixel. Rows are stored as `u64` words with the
+/// leftmost pixel in the most significant bit.
+#[derive(Clone)]
 pub struct Graphics {
-    display: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    resolution: Resolution,
+    planes: Vec<Vec<u64>>,
+    /// Bitmask of the planes that drawing, clearing and scrolling affect.
+    selected_planes: u8,
+    /// Rows changed since the last `clear_dirty`.
+    dirty: Vec<bool>,
+    frame: u64,
+}
+
+impl Default for Graphics {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Graphics {
     pub fn new() -> Graphics {
+        Graphics::with_planes(1)
+    }
+
+    pub fn with_planes(planes: usize) -> Graphics {
+        let resolution = Resolution::LOW;
         Graphics {
-            display: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            resolution,
+            planes: vec![vec![0; Graphics::words(resolution)]; planes],
+            selected_planes: 1,
+            dirty: vec![true; resolution.height],
+            frame: 0,
         }
     }
 
-    pub fn to_rgba(&self) -> Vec<u8> {
-        let mut data = Vec::with_capacity(DISPLAY_WIDTH * DISPLAY_HEIGHT * 4);
-        for y in 0..DISPLAY_HEIGHT {
-            for x in 0..DISPLAY_WIDTH {
-                data.push(if self.display[y][x] > 0 { 128 as u8 } else { 0 });
-                data.push(0);
-                data.push(0);
-                data.push(255);
+    fn words(resolution: Resolution) -> usize {
+        resolution.width / 64 * resolution.height
+    }
+
+    fn words_per_row(&self) -> usize {
+        self.resolution.width / 64
+    }
+
+    /// Appends the resolution, plane selection and bitplanes to `out`.
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.resolution.width as u16).to_le_bytes());
+        out.extend_from_slice(&(self.resolution.height as u16).to_le_bytes());
+        out.extend_from_slice(&[self.planes.len() as u8, self.selected_planes]);
+        for plane in &self.planes {
+            for word in plane {
+                out.extend_from_slice(&word.to_le_bytes());
             }
         }
-        data
     }
 
-    pub fn clear(&mut self) {
-        for y in 0..DISPLAY_HEIGHT {
-            for x in 0..DISPLAY_WIDTH {
-                self.display[y][x] = 0;
+    /// Restores a state from `save_state`, marking every row dirty.
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        let resolution = Resolution {
+            width: reader.u16()? as usize,
+            height: reader.u16()? as usize,
+        };
+        if !Resolution::ALL.contains(&resolution) {
+            return Err(Chip8Error::new(&format!(
+                "Snapshot display resolution {}x{} is not supported",
+                resolution.width, resolution.height
+            )));
+        }
+        let planes = reader.u8()? as usize;
+        self.selected_planes = reader.u8()?;
+
+        self.resolution = resolution;
+        self.planes = vec![vec![0; Graphics::words(resolution)]; planes];
+        for plane in &mut self.planes {
+            for word in plane.iter_mut() {
+                *word = reader.u64()?;
             }
         }
+        self.dirty = vec![false; resolution.height];
+        self.mark_dirty(0..resolution.height);
+        Ok(())
     }
 
-    pub fn draw(&mut self, index: usize, num_bytes: usize, x: usize, y: usize, mmu: &Mmu) -> bool {
-        let mut overwrote_pixel = false;
+    pub fn width(&self) -> usize {
+        self.resolution.width
+    }
 
-        let x_coord = x % DISPLAY_WIDTH;
-        let y_coord = y % DISPLAY_HEIGHT;
+    pub fn height(&self) -> usize {
+        self.resolution.height
+    }
 
-        for row in 0..num_bytes {
-            let bits = mmu.read8((index + row) as u16);
-            let cy = (y_coord + row) % DISPLAY_HEIGHT;
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
 
-            for col in 0..8 {
-                let cx = (x_coord + col) % DISPLAY_WIDTH;
-                let current_col = self.display[cy][cx];
-                let col = bits & (0x01 << 7 - col);
+    /// Switches resolution, clearing every plane.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        for plane in &mut self.planes {
+            *plane = vec![0; Graphics::words(resolution)];
+        }
+        self.dirty = vec![true; resolution.height];
+        self.frame += 1;
+    }
+
+    /// Counts changes to the picture. Consumers compare it with the value
+    /// they last saw to skip work when nothing changed.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
 
-                if col > 0 {
-                    if current_col > 0 {
-                        self.display[cy][cx] = 0;
-                        overwrote_pixel = true
-                    } else {
-                        self.display[cy][cx] = 1;
+    pub fn is_row_dirty(&self, y: usize) -> bool {
+        self.dirty[y]
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty.fill(false);
+    }
+
+    /// Marks every row as changed, forcing a full redraw.
+    pub fn mark_all_dirty(&mut self) {
+        self.mark_dirty(0..self.resolution.height);
+    }
+
+    /// Also marks the rows `earlier` had dirty, for when a copy of the
+    /// display is skipped and only a later one is drawn.
+    pub fn add_dirty_rows(&mut self, earlier: &Graphics) {
+        if earlier.resolution != self.resolution {
+            self.mark_all_dirty();
+            return;
+        }
+        for (dirty, earlier) in self.dirty.iter_mut().zip(&earlier.dirty) {
+            *dirty |= *earlier;
+        }
+    }
+
+    fn mark_dirty(&mut self, rows: std::ops::Range<usize>) {
+        self.dirty[rows].fill(true);
+        self.frame += 1;
+    }
+
+    pub fn plane_count(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask;
+    }
+
+    fn selected(&self) -> impl Iterator<Item = usize> {
+        let mask = self.selected_planes;
+        (0..self.planes.len()).filter(move |plane| mask & (1 << plane) != 0)
+    }
+
+    /// Renders the whole picture into `data`, resizing it to fit. Reusing the
+    /// same buffer every frame avoids allocating.
+    pub fn write_rgba(&self, palette: &Palette, data: &mut Vec<u8>) {
+        let row_size = self.width() * 4;
+        data.resize(row_size * self.height(), 0);
+        for (y, row) in data.chunks_exact_mut(row_size).enumerate() {
+            self.write_rgba_row(palette, y, row);
+        }
+    }
+
+    /// Renders row `y` into `row`, which holds `width() * 4` bytes.
+    pub fn write_rgba_row(&self, palette: &Palette, y: usize, row: &mut [u8]) {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let color = palette.color(self.pixel(x, y));
+            pixel[..3].copy_from_slice(&color);
+            pixel[3] = 255;
+        }
+    }
+
+    /// Bit n of the result is set when the pixel is on in plane n.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let word = y * self.words_per_row() + x / 64;
+        let mask = 1 << (63 - x % 64);
+        self.planes.iter().enumerate().fold(0, |value, (n, plane)| {
+            value | (((plane[word] & mask != 0) as u8) << n)
+        })
+    }
+
+    /// Sets or clears a pixel in the first plane.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let word = y * self.words_per_row() + x / 64;
+        let mask = 1 << (63 - x % 64);
+        let value = if on {
+            self.planes[0][word] | mask
+        } else {
+            self.planes[0][word] & !mask
+        };
+        if value != self.planes[0][word] {
+            self.planes[0][word] = value;
+            self.mark_dirty(y..y + 1);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for plane in self.selected() {
+            self.planes[plane].fill(0);
+        }
+        self.mark_dirty(0..self.height());
+    }
+
+    /// XORs a sprite onto every selected plane and returns whether it turned
+    /// off any pixel. Planes take consecutive sprites from `index`. A height
+    /// of 0 draws a 16x16 sprite. The sprite's origin always wraps; with
+    /// `clip` the parts that run past the right or bottom edge are dropped
+    /// instead of wrapping.
+    pub fn draw(
+        &mut self,
+        index: usize,
+        num_bytes: usize,
+        x: usize,
+        y: usize,
+        mmu: &Mmu,
+        clip: bool,
+    ) -> bool {
+        let (rows, bytes_per_row) = if num_bytes == 0 {
+            (16, 2)
+        } else {
+            (num_bytes, 1)
+        };
+        let x_coord = x % self.width();
+        let y_coord = y % self.height();
+
+        let mut overwrote_pixel = false;
+        let mut address = index;
+
+        for plane in self.selected() {
+            for row in 0..rows {
+                let mut bits = 0u64;
+                for byte in 0..bytes_per_row {
+                    bits = (bits << 8) | mmu.read8((address + byte) as u16) as u64;
+                }
+                address += bytes_per_row;
+
+                let mut cy = y_coord + row;
+                if cy >= self.height() {
+                    if clip {
+                        continue;
                     }
+                    cy %= self.height();
                 }
 
-                if cx == DISPLAY_WIDTH - 1 {
-                    break;
+                let bits = bits << (64 - 8 * bytes_per_row);
+                overwrote_pixel |= self.xor_row(plane, cy, x_coord, bits, clip);
+            }
+        }
+
+        overwrote_pixel
+    }
+
+    /// XORs `bits`, left aligned, onto a row starting at column `x`.
+    fn xor_row(&mut self, plane: usize, y: usize, x: usize, bits: u64, clip: bool) -> bool {
+        let words = self.words_per_row();
+        let row = &mut self.planes[plane][y * words..(y + 1) * words];
+        let word = x / 64;
+        let offset = (x % 64) as u32;
+
+        let mut collided = false;
+        let mut changed = false;
+        let mut xor = |word: usize, sprite: u64| {
+            collided |= row[word] & sprite != 0;
+            changed |= sprite != 0;
+            row[word] ^= sprite;
+        };
+
+        xor(word, bits >> offset);
+        if offset > 0 {
+            let spill = bits << (64 - offset);
+            if word + 1 < words {
+                xor(word + 1, spill);
+            } else if !clip {
+                xor(0, spill);
+            }
+        }
+
+        if changed {
+            self.mark_dirty(y..y + 1);
+        }
+        collided
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        let words = self.words_per_row();
+        for plane in self.selected() {
+            let plane = &mut self.planes[plane];
+            let shift = (lines * words).min(plane.len());
+            plane.rotate_right(shift);
+            plane[..shift].fill(0);
+        }
+        self.mark_dirty(0..self.height());
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let words = self.words_per_row();
+        for plane in self.selected() {
+            let plane = &mut self.planes[plane];
+            let shift = (lines * words).min(plane.len());
+            plane.rotate_left(shift);
+            let length = plane.len();
+            plane[length - shift..].fill(0);
+        }
+        self.mark_dirty(0..self.height());
+    }
+
+    /// Scrolls 4 pixels right.
+    pub fn scroll_right(&mut self) {
+        let words = self.words_per_row();
+        for plane in self.selected() {
+            for row in self.planes[plane].chunks_mut(words) {
+                let mut carry = 0;
+                for word in row.iter_mut() {
+                    let value = *word;
+                    *word = (value >> 4) | carry;
+                    carry = value << 60;
                 }
             }
-            if cy == DISPLAY_HEIGHT - 1 {
-                break;
+        }
+        self.mark_dirty(0..self.height());
+    }
+
+    /// Scrolls 4 pixels left.
+    pub fn scroll_left(&mut self) {
+        let words = self.words_per_row();
+        for plane in self.selected() {
+            for row in self.planes[plane].chunks_mut(words) {
+                let mut carry = 0;
+                for word in row.iter_mut().rev() {
+                    let value = *word;
+                    *word = (value << 4) | carry;
+                    carry = value >> 60;
+                }
             }
         }
-        overwrote_pixel
+        self.mark_dirty(0..self.height());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_and_wraps_at_every_resolution() {
+        let mut mmu = Mmu::new();
+        for i in 0..32 {
+            mmu.write8(0x300 + i, 0xFF);
+        }
+
+        for resolution in Resolution::ALL {
+            let mut graphics = Graphics::new();
+            graphics.set_resolution(resolution);
+            let (x, y) = (resolution.width - 8, resolution.height - 8);
+
+            // A 16x16 sprite 8 pixels from the bottom right corner wraps
+            assert!(!graphics.draw(0x300, 0, x, y, &mmu, false));
+            assert_eq!(graphics.pixel(x, y), 1);
+            assert_eq!(graphics.pixel(7, 7), 1);
+            assert_eq!(graphics.pixel(8, 8), 0);
+            let mut data = vec![];
+            graphics.write_rgba(&Palette::default(), &mut data);
+            assert_eq!(data.len(), resolution.width * resolution.height * 4);
+
+            assert!(graphics.draw(0x300, 0, x, y, &mmu, false));
+            assert_eq!(graphics.pixel(x, y), 0);
+            assert_eq!(graphics.pixel(7, 7), 0);
+        }
+    }
+
+    #[test]
+    fn state_round_trips_at_every_resolution() {
+        let mut mmu = Mmu::new();
+        mmu.write8(0x300, 0xA5);
+
+        for resolution in Resolution::ALL {
+            let mut graphics = Graphics::with_planes(2);
+            graphics.set_resolution(resolution);
+            graphics.select_planes(3);
+            graphics.draw(
+                0x300,
+                0,
+                resolution.width - 4,
+                resolution.height - 1,
+                &mmu,
+                false,
+            );
+            let mut state = vec![];
+            graphics.save_state(&mut state);
+
+            let mut restored = Graphics::new();
+            restored.load_state(&mut StateReader::new(&state)).unwrap();
+            assert_eq!(restored.resolution(), resolution);
+            assert_eq!(restored.plane_count(), 2);
+            let mut again = vec![];
+            restored.save_state(&mut again);
+            assert_eq!(again, state);
+        }
+
+        let mut state = vec![];
+        Graphics::new().save_state(&mut state);
+        state[2..4].copy_from_slice(&0u16.to_le_bytes());
+        assert!(Graphics::new()
+            .load_state(&mut StateReader::new(&state))
+            .is_err());
     }
 }
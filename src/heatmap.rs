@@ -0,0 +1,77 @@
+use crate::mmu::MEMORY_SIZE;
+
+/// Recent memory activity per address. Each access sets the intensity of its
+/// kind to 1.0 and `fade` decays it towards 0 over time.
+#[derive(Clone)]
+pub struct Heatmap {
+    reads: Vec<f32>,
+    writes: Vec<f32>,
+    executes: Vec<f32>,
+    last_pc: Vec<Option<u16>>,
+    pc: u16,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Heatmap {
+        Heatmap {
+            reads: vec![0.0; MEMORY_SIZE],
+            writes: vec![0.0; MEMORY_SIZE],
+            executes: vec![0.0; MEMORY_SIZE],
+            last_pc: vec![None; MEMORY_SIZE],
+            pc: 0,
+        }
+    }
+
+    /// Sets the address of the instruction responsible for the following accesses.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn read(&mut self, address: u16) {
+        self.reads[address as usize] = 1.0;
+        self.last_pc[address as usize] = Some(self.pc);
+    }
+
+    pub fn write(&mut self, address: u16) {
+        self.writes[address as usize] = 1.0;
+        self.last_pc[address as usize] = Some(self.pc);
+    }
+
+    pub fn execute(&mut self, address: u16) {
+        self.executes[address as usize] = 1.0;
+        self.last_pc[address as usize] = Some(self.pc);
+    }
+
+    /// Scales every intensity by `factor`, which should be in `0.0..1.0`.
+    pub fn fade(&mut self, factor: f32) {
+        for value in self
+            .reads
+            .iter_mut()
+            .chain(self.writes.iter_mut())
+            .chain(self.executes.iter_mut())
+        {
+            *value *= factor;
+        }
+    }
+
+    pub fn last_pc(&self, address: u16) -> Option<u16> {
+        self.last_pc[address as usize]
+    }
+
+    /// Writes in red, reads in green and executes in blue.
+    pub fn color(&self, address: u16) -> [f32; 4] {
+        let address = address as usize;
+        [
+            self.writes[address],
+            self.reads[address],
+            self.executes[address],
+            1.0,
+        ]
+    }
+}
@@ -1,8 +1,19 @@
+#[derive(Copy, Clone)]
 pub struct Input {
     keys: [bool; 16],
 }
 
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Input {
+    pub fn new() -> Input {
+        Input { keys: [false; 16] }
+    }
+
     pub fn key_pressed(&self, key: u8) -> bool {
         self.keys[key as usize]
     }
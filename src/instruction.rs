@@ -1,5 +1,7 @@
 use crate::cpu::Register;
+use crate::platform::Platform;
 
+#[derive(Copy, Clone, Debug)]
 pub enum Instruction {
     Cls,
     Ret,
@@ -15,9 +17,9 @@ pub enum Instruction {
     XorRegister(Register, Register),
     AddRegister(Register, Register),
     SubRegister(Register, Register),
-    Shr(Register),
+    Shr(Register, Register),
     Subn(Register, Register),
-    Shl(Register),
+    Shl(Register, Register),
     SkipInstructionRegisterEqual(Register, Register),
     SkipInstructionRegisterNotEqual(Register, Register),
     LoadIndex(u16),
@@ -35,6 +37,22 @@ pub enum Instruction {
     StoreBcd(Register),
     StoreRegisters(Register /* last register */),
     LoadRegisters(Register /* last register */),
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    LoadBigSpriteIndex(Register),
+    StoreFlags(Register /* last register */),
+    LoadFlags(Register /* last register */),
+    ScrollUp(u8),
+    StoreRegisterRange(Register, Register),
+    LoadRegisterRange(Register, Register),
+    LongIndex, /* address is in the following word */
+    SelectPlanes(u8),
+    LoadAudio,
+    SetPitch(Register),
     Invalid,
 }
 
@@ -54,6 +72,22 @@ impl Instruction {
         if value == 0x00EE {
             return Instruction::Ret;
         }
+        match value {
+            0x00FB => return Instruction::ScrollRight,
+            0x00FC => return Instruction::ScrollLeft,
+            0x00FD => return Instruction::Exit,
+            0x00FE => return Instruction::LowRes,
+            0x00FF => return Instruction::HighRes,
+            0xF000 => return Instruction::LongIndex,
+            0xF002 => return Instruction::LoadAudio,
+            _ => {}
+        }
+        if value & 0xFFF0 == 0x00C0 {
+            return Instruction::ScrollDown((value & 0xF) as u8);
+        }
+        if value & 0xFFF0 == 0x00D0 {
+            return Instruction::ScrollUp((value & 0xF) as u8);
+        }
         match value & 0xF000 {
             0x1000 => return Instruction::Jmp(value & 0x0FFF),
             0x2000 => return Instruction::Call(value & 0x0FFF),
@@ -78,6 +112,12 @@ impl Instruction {
         if value & 0xF00F == 0x5000 {
             return Instruction::SkipInstructionRegisterEqual(x_value(value), y_value(value));
         }
+        if value & 0xF00F == 0x5002 {
+            return Instruction::StoreRegisterRange(x_value(value), y_value(value));
+        }
+        if value & 0xF00F == 0x5003 {
+            return Instruction::LoadRegisterRange(x_value(value), y_value(value));
+        }
 
         if value & 0xF00F == 0x8000 {
             return Instruction::LoadRegister(x_value(value), y_value(value));
@@ -98,13 +138,13 @@ impl Instruction {
             return Instruction::SubRegister(x_value(value), y_value(value));
         }
         if value & 0xF00F == 0x8006 {
-            return Instruction::Shr(x_value(value));
+            return Instruction::Shr(x_value(value), y_value(value));
         }
         if value & 0xF00F == 0x8007 {
             return Instruction::Subn(x_value(value), y_value(value));
         }
         if value & 0xF00F == 0x800E {
-            return Instruction::Shl(x_value(value));
+            return Instruction::Shl(x_value(value), y_value(value));
         }
         if value & 0xF00F == 0x9000 {
             return Instruction::SkipInstructionRegisterNotEqual(x_value(value), y_value(value));
@@ -142,7 +182,125 @@ impl Instruction {
         if value & 0xF0FF == 0xF065 {
             return Instruction::LoadRegisters(x_value(value));
         }
+        if value & 0xF0FF == 0xF001 {
+            return Instruction::SelectPlanes(((value >> 8) & 0xF) as u8);
+        }
+        if value & 0xF0FF == 0xF030 {
+            return Instruction::LoadBigSpriteIndex(x_value(value));
+        }
+        if value & 0xF0FF == 0xF03A {
+            return Instruction::SetPitch(x_value(value));
+        }
+        if value & 0xF0FF == 0xF075 {
+            return Instruction::StoreFlags(x_value(value));
+        }
+        if value & 0xF0FF == 0xF085 {
+            return Instruction::LoadFlags(x_value(value));
+        }
 
         Instruction::Invalid
     }
+
+    /// Size in bytes, including any operand words that follow the opcode.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LongIndex => 4,
+            _ => 2,
+        }
+    }
+
+    /// The first platform that defines the instruction.
+    pub fn platform(&self) -> Platform {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::LoadBigSpriteIndex(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_)
+            | Instruction::Draw(_, _, 0) => Platform::SuperChip,
+            Instruction::ScrollUp(_)
+            | Instruction::StoreRegisterRange(..)
+            | Instruction::LoadRegisterRange(..)
+            | Instruction::LongIndex
+            | Instruction::SelectPlanes(_)
+            | Instruction::LoadAudio
+            | Instruction::SetPitch(_) => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+
+    /// Whether the instruction conditionally skips the one that follows it.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipInstructionEqual(..)
+                | Instruction::SkipInstructionNotEqual(..)
+                | Instruction::SkipInstructionRegisterEqual(..)
+                | Instruction::SkipInstructionRegisterNotEqual(..)
+                | Instruction::SkipIfPressed(..)
+                | Instruction::SkipIfNotPressed(..)
+        )
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jmp(addr) => write!(f, "JP {:#05X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL {:#05X}", addr),
+            Instruction::SkipInstructionEqual(x, kk) => write!(f, "SE {}, {:#04X}", x, kk),
+            Instruction::SkipInstructionNotEqual(x, kk) => write!(f, "SNE {}, {:#04X}", x, kk),
+            Instruction::LoadConstant(x, kk) => write!(f, "LD {}, {:#04X}", x, kk),
+            Instruction::Add(x, kk) => write!(f, "ADD {}, {:#04X}", x, kk),
+            Instruction::LoadRegister(x, y) => write!(f, "LD {}, {}", x, y),
+            Instruction::OrRegister(x, y) => write!(f, "OR {}, {}", x, y),
+            Instruction::AndRegister(x, y) => write!(f, "AND {}, {}", x, y),
+            Instruction::XorRegister(x, y) => write!(f, "XOR {}, {}", x, y),
+            Instruction::AddRegister(x, y) => write!(f, "ADD {}, {}", x, y),
+            Instruction::SubRegister(x, y) => write!(f, "SUB {}, {}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR {}, {}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN {}, {}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL {}, {}", x, y),
+            Instruction::SkipInstructionRegisterEqual(x, y) => write!(f, "SE {}, {}", x, y),
+            Instruction::SkipInstructionRegisterNotEqual(x, y) => write!(f, "SNE {}, {}", x, y),
+            Instruction::LoadIndex(addr) => write!(f, "LD I, {:#05X}", addr),
+            Instruction::JumpV0(addr) => write!(f, "JP V0, {:#05X}", addr),
+            Instruction::Random(x, kk) => write!(f, "RND {}, {:#04X}", x, kk),
+            Instruction::Draw(x, y, n) => write!(f, "DRW {}, {}, {}", x, y, n),
+            Instruction::SkipIfPressed(x) => write!(f, "SKP {}", x),
+            Instruction::SkipIfNotPressed(x) => write!(f, "SKNP {}", x),
+            Instruction::LoadDelayTimer(x) => write!(f, "LD {}, DT", x),
+            Instruction::WaitForKeyPress(x) => write!(f, "LD {}, K", x),
+            Instruction::StoreDelayTimer(x) => write!(f, "LD DT, {}", x),
+            Instruction::StoreSoundTimer(x) => write!(f, "LD ST, {}", x),
+            Instruction::AddIndex(x) => write!(f, "ADD I, {}", x),
+            Instruction::LoadSpriteIndex(x) => write!(f, "LD F, {}", x),
+            Instruction::StoreBcd(x) => write!(f, "LD B, {}", x),
+            Instruction::StoreRegisters(x) => write!(f, "LD [I], {}", x),
+            Instruction::LoadRegisters(x) => write!(f, "LD {}, [I]", x),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::LoadBigSpriteIndex(x) => write!(f, "LD HF, {}", x),
+            Instruction::StoreFlags(x) => write!(f, "LD R, {}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD {}, R", x),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::StoreRegisterRange(x, y) => write!(f, "SAVE {} - {}", x, y),
+            Instruction::LoadRegisterRange(x, y) => write!(f, "LOAD {} - {}", x, y),
+            Instruction::LongIndex => write!(f, "LD I, long"),
+            Instruction::SelectPlanes(n) => write!(f, "PLANE {}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::SetPitch(x) => write!(f, "PITCH {}", x),
+            Instruction::Invalid => write!(f, "???"),
+        }
+    }
 }
@@ -0,0 +1,316 @@
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::{Keycode, Scancode};
+
+use chip8::error::Chip8Error;
+
+/// A host key, either by the symbol it produces or by its physical position,
+/// or a game controller button or stick direction.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HostKey {
+    Keycode(Keycode),
+    Scancode(Scancode),
+    Button(Button),
+    /// An axis pushed past the threshold, towards positive values if set.
+    Axis(Axis, bool),
+}
+
+/// Default fraction of a stick's travel that counts as a key press.
+pub const DEFAULT_AXIS_THRESHOLD: f32 = 0.5;
+
+impl HostKey {
+    /// An SDL key name such as `W` or `Keypad_5`, with underscores for
+    /// spaces. A `scan:` prefix names a physical key instead, so `scan:W`
+    /// stays put on AZERTY keyboards. A `pad:` prefix names an SDL controller
+    /// button such as `pad:dpup`, or an axis direction such as `pad:leftx+`.
+    pub fn from_name(name: &str) -> Option<HostKey> {
+        if let Some(name) = name.strip_prefix("pad:") {
+            return match (name.strip_suffix('+'), name.strip_suffix('-')) {
+                (Some(axis), _) => Axis::from_string(axis).map(|axis| HostKey::Axis(axis, true)),
+                (_, Some(axis)) => Axis::from_string(axis).map(|axis| HostKey::Axis(axis, false)),
+                _ => Button::from_string(name).map(HostKey::Button),
+            };
+        }
+
+        let name = name.replace('_', " ");
+        match name.strip_prefix("scan:") {
+            Some(name) => Scancode::from_name(name).map(HostKey::Scancode),
+            None => Keycode::from_name(&name).map(HostKey::Keycode),
+        }
+    }
+}
+
+impl std::fmt::Display for HostKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostKey::Keycode(keycode) => write!(f, "{}", keycode.name()),
+            HostKey::Scancode(scancode) => write!(f, "scan:{}", scancode.name()),
+            HostKey::Button(button) => write!(f, "pad:{}", button.string()),
+            HostKey::Axis(axis, positive) => {
+                write!(
+                    f,
+                    "pad:{}{}",
+                    axis.string(),
+                    if *positive { '+' } else { '-' }
+                )
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum KeymapPreset {
+    /// The VIP keypad laid over 1234/QWER/ASDF/ZXCV by position.
+    Standard,
+    /// The VIP keypad laid over the numeric keypad.
+    Numpad,
+    /// Each hex key on the host key with the same label, as printed on the
+    /// VIP's keypad.
+    Hex,
+}
+
+impl KeymapPreset {
+    pub fn from_name(name: &str) -> Option<KeymapPreset> {
+        match name {
+            "standard" => Some(KeymapPreset::Standard),
+            "numpad" => Some(KeymapPreset::Numpad),
+            "hex" => Some(KeymapPreset::Hex),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for KeymapPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeymapPreset::Standard => write!(f, "standard"),
+            KeymapPreset::Numpad => write!(f, "numpad"),
+            KeymapPreset::Hex => write!(f, "hex"),
+        }
+    }
+}
+
+/// Maps host keys onto the 16 keypad keys. A keypad key may have any number
+/// of host keys and stays pressed while any of them is held.
+pub struct Keymap {
+    bindings: Vec<(HostKey, u8)>,
+    held: Vec<bool>,
+    axis_threshold: f32,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(KeymapPreset::Standard)
+    }
+}
+
+impl Keymap {
+    pub fn new(preset: KeymapPreset) -> Keymap {
+        let mut keymap = Keymap {
+            bindings: vec![],
+            held: vec![],
+            axis_threshold: DEFAULT_AXIS_THRESHOLD,
+        };
+
+        // Every preset puts the controller on the W/A/S/D cluster, which
+        // most games use for movement, with Q and E on the face buttons.
+        let controller = [
+            (HostKey::Button(Button::DPadUp), 0x5),
+            (HostKey::Button(Button::DPadLeft), 0x7),
+            (HostKey::Button(Button::DPadDown), 0x8),
+            (HostKey::Button(Button::DPadRight), 0x9),
+            (HostKey::Axis(Axis::LeftY, false), 0x5),
+            (HostKey::Axis(Axis::LeftX, false), 0x7),
+            (HostKey::Axis(Axis::LeftY, true), 0x8),
+            (HostKey::Axis(Axis::LeftX, true), 0x9),
+            (HostKey::Button(Button::A), 0x6),
+            (HostKey::Button(Button::B), 0x4),
+        ];
+        for (host, key) in controller {
+            keymap.bind(host, key);
+        }
+
+        match preset {
+            KeymapPreset::Standard => {
+                let layout = [
+                    (Scancode::Num1, 0x1),
+                    (Scancode::Num2, 0x2),
+                    (Scancode::Num3, 0x3),
+                    (Scancode::Num4, 0xC),
+                    (Scancode::Q, 0x4),
+                    (Scancode::W, 0x5),
+                    (Scancode::E, 0x6),
+                    (Scancode::R, 0xD),
+                    (Scancode::A, 0x7),
+                    (Scancode::S, 0x8),
+                    (Scancode::D, 0x9),
+                    (Scancode::F, 0xE),
+                    (Scancode::Z, 0xA),
+                    (Scancode::X, 0x0),
+                    (Scancode::C, 0xB),
+                    (Scancode::V, 0xF),
+                ];
+                for (scancode, key) in layout {
+                    keymap.bind(HostKey::Scancode(scancode), key);
+                }
+            }
+            KeymapPreset::Numpad => {
+                let layout = [
+                    (Scancode::Kp7, 0x1),
+                    (Scancode::Kp8, 0x2),
+                    (Scancode::Kp9, 0x3),
+                    (Scancode::KpMinus, 0xC),
+                    (Scancode::Kp4, 0x4),
+                    (Scancode::Kp5, 0x5),
+                    (Scancode::Kp6, 0x6),
+                    (Scancode::KpPlus, 0xD),
+                    (Scancode::Kp1, 0x7),
+                    (Scancode::Kp2, 0x8),
+                    (Scancode::Kp3, 0x9),
+                    (Scancode::KpEnter, 0xE),
+                    (Scancode::KpDivide, 0xA),
+                    (Scancode::Kp0, 0x0),
+                    (Scancode::KpPeriod, 0xB),
+                    (Scancode::KpMultiply, 0xF),
+                ];
+                for (scancode, key) in layout {
+                    keymap.bind(HostKey::Scancode(scancode), key);
+                }
+            }
+            KeymapPreset::Hex => {
+                let labels = [
+                    Keycode::Num0,
+                    Keycode::Num1,
+                    Keycode::Num2,
+                    Keycode::Num3,
+                    Keycode::Num4,
+                    Keycode::Num5,
+                    Keycode::Num6,
+                    Keycode::Num7,
+                    Keycode::Num8,
+                    Keycode::Num9,
+                    Keycode::A,
+                    Keycode::B,
+                    Keycode::C,
+                    Keycode::D,
+                    Keycode::E,
+                    Keycode::F,
+                ];
+                for (key, keycode) in labels.into_iter().enumerate() {
+                    keymap.bind(HostKey::Keycode(keycode), key as u8);
+                }
+            }
+        }
+        keymap
+    }
+
+    /// Sets how far, from 0.0 to 1.0, a stick must move to press a key.
+    pub fn set_axis_threshold(&mut self, threshold: f32) {
+        self.axis_threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    pub fn bind(&mut self, host: HostKey, key: u8) {
+        self.bindings.push((host, key & 0xF));
+        self.held.push(false);
+    }
+
+    /// Removes every host key bound to `key`.
+    pub fn unbind(&mut self, key: u8) {
+        let mut i = 0;
+        while i < self.bindings.len() {
+            if self.bindings[i].1 == key {
+                self.bindings.remove(i);
+                self.held.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Whether any host key bound to `key` is held.
+    pub fn is_held(&self, key: u8) -> bool {
+        self.bindings
+            .iter()
+            .zip(&self.held)
+            .any(|((_, bound), &held)| *bound == key && held)
+    }
+
+    /// Host keys bound to `key`.
+    pub fn host_keys(&self, key: u8) -> impl Iterator<Item = HostKey> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| *bound == key)
+            .map(|(host, _)| *host)
+    }
+
+    /// Replaces the bindings of each listed key with the given host keys, as
+    /// stored in the rom database. A key listed twice gets both host keys.
+    pub fn apply_overrides(&mut self, overrides: &[(u8, String)]) -> Result<(), Chip8Error> {
+        for (key, _) in overrides {
+            self.unbind(*key);
+        }
+        for (key, name) in overrides {
+            let host = HostKey::from_name(name)
+                .ok_or_else(|| Chip8Error::new(&format!("Unknown key {}", name)))?;
+            self.bind(host, *key);
+        }
+        Ok(())
+    }
+
+    /// Updates the held state of a host key and returns the keypad keys whose
+    /// state it decides, along with whether each is now pressed.
+    pub fn handle_key(
+        &mut self,
+        keycode: Option<Keycode>,
+        scancode: Option<Scancode>,
+        pressed: bool,
+    ) -> Vec<(u8, bool)> {
+        self.update(|host| match host {
+            HostKey::Keycode(bound) => (keycode == Some(*bound)).then_some(pressed),
+            HostKey::Scancode(bound) => (scancode == Some(*bound)).then_some(pressed),
+            _ => None,
+        })
+    }
+
+    pub fn handle_button(&mut self, button: Button, pressed: bool) -> Vec<(u8, bool)> {
+        self.update(|host| (*host == HostKey::Button(button)).then_some(pressed))
+    }
+
+    pub fn handle_axis(&mut self, axis: Axis, value: i16) -> Vec<(u8, bool)> {
+        let value = value as f32 / i16::MAX as f32;
+        let threshold = self.axis_threshold;
+        self.update(|host| match host {
+            HostKey::Axis(bound, true) if *bound == axis => Some(value >= threshold),
+            HostKey::Axis(bound, false) if *bound == axis => Some(value <= -threshold),
+            _ => None,
+        })
+    }
+
+    /// Lets go of every controller input, for when a controller disconnects
+    /// with buttons held.
+    pub fn release_controllers(&mut self) -> Vec<(u8, bool)> {
+        self.update(|host| match host {
+            HostKey::Button(_) | HostKey::Axis(..) => Some(false),
+            _ => None,
+        })
+    }
+
+    /// Sets the held state of every binding `state` returns one for.
+    fn update(&mut self, state: impl Fn(&HostKey) -> Option<bool>) -> Vec<(u8, bool)> {
+        let mut changed = Vec::new();
+        for (i, (host, key)) in self.bindings.iter().enumerate() {
+            if let Some(held) = state(host) {
+                if self.held[i] != held {
+                    self.held[i] = held;
+                    changed.push(*key);
+                }
+            }
+        }
+
+        changed.sort();
+        changed.dedup();
+        changed
+            .into_iter()
+            .map(|key| (key, self.is_held(key)))
+            .collect()
+    }
+}
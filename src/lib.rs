@@ -0,0 +1,26 @@
+pub mod analysis;
+pub mod audio;
+pub mod capture;
+pub mod cdp1802;
+pub mod coverage;
+pub mod cpu;
+pub mod detect;
+pub mod error;
+pub mod graphics;
+pub mod heatmap;
+pub mod input;
+pub mod instruction;
+pub mod lint;
+pub mod machine;
+pub mod mmu;
+pub mod movie;
+pub mod platform;
+pub mod postprocess;
+pub mod quirks;
+pub mod random;
+pub mod romdb;
+pub mod scheduler;
+pub mod snapshot;
+pub mod symbols;
+pub mod timing;
+pub mod vip;
@@ -0,0 +1,517 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::analysis;
+use crate::instruction::Instruction;
+use crate::mmu::{Mmu, FONT_SIZE, PROGRAM_START};
+use crate::quirks::QuirksPreset;
+
+/// Nesting depth the COSMAC VIP interpreter reserves stack space for.
+const STACK_LIMIT: usize = 12;
+
+pub struct Finding {
+    pub address: u16,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:03X}: {}", self.address, self.message)
+    }
+}
+
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+    /// Preset the quirk-dependent code most likely expects.
+    pub preset: QuirksPreset,
+}
+
+/// What is statically known about I on entry to an instruction.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Index {
+    Unset,
+    Known(u16),
+    Unknown,
+}
+
+/// Instructions reached from a subroutine entry without following calls.
+struct Body {
+    calls: BTreeSet<u16>,
+    returns: Vec<u16>,
+}
+
+struct Linter<'a> {
+    mmu: &'a Mmu,
+    code: BTreeSet<u16>,
+    findings: Vec<Finding>,
+    data: BTreeSet<u16>,
+    chip8_votes: usize,
+    super_chip_votes: usize,
+}
+
+/// Looks for likely bugs in the loaded ROM without running it.
+pub fn lint(mmu: &Mmu) -> LintReport {
+    let mut linter = Linter {
+        mmu,
+        code: analysis::reachable_code(mmu, PROGRAM_START),
+        findings: vec![],
+        data: BTreeSet::new(),
+        chip8_votes: 0,
+        super_chip_votes: 0,
+    };
+
+    linter.check_opcodes();
+    linter.check_sprites();
+    linter.check_jumps();
+    linter.check_stack();
+    linter.check_index_after_memory();
+
+    linter.findings.sort_by_key(|finding| finding.address);
+    LintReport {
+        preset: if linter.super_chip_votes > linter.chip8_votes {
+            QuirksPreset::SuperChip
+        } else {
+            QuirksPreset::Chip8
+        },
+        findings: linter.findings,
+    }
+}
+
+impl<'a> Linter<'a> {
+    fn decode(&self, address: u16) -> Instruction {
+        Instruction::decode(self.mmu.peek16(address))
+    }
+
+    fn report(&mut self, address: u16, message: String) {
+        self.findings.push(Finding { address, message });
+    }
+
+    fn check_opcodes(&mut self) {
+        for address in self.code.clone() {
+            match self.decode(address) {
+                Instruction::Invalid => {
+                    let message = format!("invalid opcode {:04X}", self.mmu.peek16(address));
+                    self.report(address, message);
+                }
+                Instruction::Shr(x, y) | Instruction::Shl(x, y) if x != y => {
+                    self.chip8_votes += 1;
+                    self.report(
+                        address,
+                        format!("shift of {} into {} depends on the shift quirk", y, x),
+                    );
+                }
+                Instruction::JumpV0(target) if target & 0xF00 != 0 => {
+                    self.report(
+                        address,
+                        String::from("BNNN with a non-zero X depends on the jump quirk"),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_jumps(&mut self) {
+        let end = self.mmu.rom_end();
+
+        for address in self.code.clone() {
+            let instruction = self.decode(address);
+            let jump_target = match instruction {
+                Instruction::Jmp(target) | Instruction::Call(target) => Some(target),
+                _ => None,
+            };
+
+            for next in analysis::successors(self.mmu, address, &instruction) {
+                let in_program = next >= PROGRAM_START && next + 1 < end;
+                if Some(next) == jump_target {
+                    if !in_program {
+                        let message = format!("jumps outside the loaded program to {:03X}", next);
+                        self.report(address, message);
+                    } else if self.data.contains(&next) {
+                        self.report(address, format!("jumps into sprite data at {:03X}", next));
+                    }
+                } else if !in_program {
+                    self.report(
+                        address,
+                        String::from("execution runs past the end of the ROM"),
+                    );
+                }
+            }
+        }
+    }
+
+    fn body(&self, entry: u16) -> Body {
+        let mut body = Body {
+            calls: BTreeSet::new(),
+            returns: vec![],
+        };
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![entry];
+
+        while let Some(address) = pending.pop() {
+            if !self.code.contains(&address) || !visited.insert(address) {
+                continue;
+            }
+            match self.decode(address) {
+                Instruction::Call(target) => {
+                    body.calls.insert(target);
+                    pending.push(address + 2);
+                }
+                Instruction::Ret => body.returns.push(address),
+                instruction => {
+                    pending.extend(analysis::successors(self.mmu, address, &instruction))
+                }
+            }
+        }
+
+        body
+    }
+
+    fn check_stack(&mut self) {
+        let main = self.body(PROGRAM_START);
+        for &address in &main.returns {
+            self.report(address, String::from("RET with an empty stack"));
+        }
+
+        let mut bodies = HashMap::new();
+        let mut pending: Vec<u16> = main.calls.iter().copied().collect();
+        while let Some(entry) = pending.pop() {
+            if bodies.contains_key(&entry) || !self.code.contains(&entry) {
+                continue;
+            }
+            let body = self.body(entry);
+            if body.returns.is_empty() {
+                self.report(entry, String::from("subroutine never returns"));
+            }
+            pending.extend(body.calls.iter().copied());
+            bodies.insert(entry, body);
+        }
+
+        let mut depths = HashMap::new();
+        let mut recursive = BTreeSet::new();
+        let depth = main
+            .calls
+            .iter()
+            .map(|&entry| call_depth(entry, &bodies, &mut depths, &mut vec![], &mut recursive))
+            .max()
+            .unwrap_or(0);
+
+        for entry in recursive {
+            self.report(
+                entry,
+                String::from("recursive subroutine may overflow the stack"),
+            );
+        }
+        if depth > STACK_LIMIT {
+            self.report(
+                PROGRAM_START,
+                format!(
+                    "subroutines nest {} deep, more than the {} levels of the stack",
+                    depth, STACK_LIMIT
+                ),
+            );
+        }
+    }
+
+    /// Tracks I through the program to find sprites drawn from memory that is
+    /// never loaded or written.
+    fn check_sprites(&mut self) {
+        let mut states = HashMap::new();
+        let mut pending = vec![PROGRAM_START];
+        states.insert(PROGRAM_START, Index::Unset);
+
+        while let Some(address) = pending.pop() {
+            let instruction = self.decode(address);
+            let index = match (instruction, states[&address]) {
+                (Instruction::LoadIndex(value), _) => Index::Known(value),
+                (
+                    Instruction::AddIndex(_)
+                    | Instruction::LoadSpriteIndex(_)
+                    | Instruction::StoreRegisters(_)
+                    | Instruction::LoadRegisters(_),
+                    _,
+                ) => Index::Unknown,
+                (_, index) => index,
+            };
+
+            for next in analysis::successors(self.mmu, address, &instruction) {
+                if !self.code.contains(&next) {
+                    continue;
+                }
+                // A subroutine may leave I anywhere
+                let index = match instruction {
+                    Instruction::Call(target) if target != next => Index::Unknown,
+                    _ => index,
+                };
+                let merged = match states.get(&next) {
+                    Some(&previous) if previous != index => Index::Unknown,
+                    _ => index,
+                };
+                if states.insert(next, merged) != Some(merged) {
+                    pending.push(next);
+                }
+            }
+        }
+
+        let mut written = BTreeSet::new();
+        let mut unknown_writes = false;
+        for (&address, &index) in &states {
+            let length = match self.decode(address) {
+                Instruction::StoreBcd(_) => 3,
+                Instruction::StoreRegisters(x) => x.to_index() as u16 + 1,
+                _ => continue,
+            };
+            match index {
+                Index::Known(start) => written.extend(start..start + length),
+                _ => unknown_writes = true,
+            }
+        }
+
+        let end = self.mmu.rom_end();
+        for (&address, &index) in &states {
+            let Instruction::Draw(_, _, rows) = self.decode(address) else {
+                continue;
+            };
+            match index {
+                Index::Unset => self.report(address, String::from("DRW before I is loaded")),
+                Index::Known(start) => {
+                    let sprite = start..start + rows as u16;
+                    self.data.extend(sprite.clone());
+
+                    let uninitialised = sprite.clone().find(|byte| {
+                        (*byte as usize) >= FONT_SIZE
+                            && !(PROGRAM_START..end).contains(byte)
+                            && !written.contains(byte)
+                    });
+                    if let (Some(byte), false) = (uninitialised, unknown_writes) {
+                        self.report(
+                            address,
+                            format!("DRW reads uninitialised memory at {:03X}", byte),
+                        );
+                    }
+                }
+                Index::Unknown => {}
+            }
+        }
+    }
+
+    /// FX55 and FX65 move I on some interpreters, so code that keeps using I
+    /// afterwards without reloading it depends on the memory quirk.
+    fn check_index_after_memory(&mut self) {
+        for address in self.code.clone() {
+            let instruction = self.decode(address);
+            if !matches!(
+                instruction,
+                Instruction::StoreRegisters(_) | Instruction::LoadRegisters(_)
+            ) {
+                continue;
+            }
+
+            let mut visited = BTreeSet::new();
+            let mut pending = analysis::successors(self.mmu, address, &instruction);
+            while let Some(next) = pending.pop() {
+                if !self.code.contains(&next) || !visited.insert(next) {
+                    continue;
+                }
+                match self.decode(next) {
+                    Instruction::LoadIndex(_) | Instruction::LoadSpriteIndex(_) => {}
+                    Instruction::Draw(..)
+                    | Instruction::AddIndex(_)
+                    | Instruction::StoreBcd(_)
+                    | Instruction::StoreRegisters(_)
+                    | Instruction::LoadRegisters(_) => {
+                        self.super_chip_votes += 1;
+                        self.report(
+                            next,
+                            format!(
+                                "uses I after {:03X} without reloading it, which depends on the memory quirk",
+                                address
+                            ),
+                        );
+                    }
+                    other => pending.extend(analysis::successors(self.mmu, next, &other)),
+                }
+            }
+        }
+    }
+}
+
+fn call_depth(
+    entry: u16,
+    bodies: &HashMap<u16, Body>,
+    depths: &mut HashMap<u16, usize>,
+    path: &mut Vec<u16>,
+    recursive: &mut BTreeSet<u16>,
+) -> usize {
+    if let Some(&depth) = depths.get(&entry) {
+        return depth;
+    }
+    let Some(body) = bodies.get(&entry) else {
+        return 0;
+    };
+    if path.contains(&entry) {
+        recursive.insert(entry);
+        return 0;
+    }
+
+    path.push(entry);
+    let deepest = body
+        .calls
+        .iter()
+        .map(|&callee| call_depth(callee, bodies, depths, path, recursive))
+        .max()
+        .unwrap_or(0);
+    path.pop();
+
+    depths.insert(entry, deepest + 1);
+    deepest + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_rom(rom: &[u8]) -> LintReport {
+        let mut mmu = Mmu::new();
+        mmu.load_rom(rom.to_vec());
+        lint(&mmu)
+    }
+
+    fn findings(rom: &[u8]) -> Vec<String> {
+        lint_rom(rom)
+            .findings
+            .iter()
+            .map(|finding| finding.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn clean_rom() {
+        // I = sprite, draw it, loop
+        let report = lint_rom(&[0xA2, 0x06, 0xD0, 0x11, 0x12, 0x04, 0xF0]);
+        assert!(report.findings.is_empty());
+        assert_eq!(report.preset, QuirksPreset::Chip8);
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        assert_eq!(findings(&[0x50, 0x11]), vec!["200: invalid opcode 5011"]);
+    }
+
+    #[test]
+    fn shift_quirk_votes_chip8() {
+        let report = lint_rom(&[0x81, 0x26, 0x12, 0x02]);
+        assert_eq!(
+            report.findings[0].to_string(),
+            "200: shift of V2 into V1 depends on the shift quirk"
+        );
+        assert_eq!(report.preset, QuirksPreset::Chip8);
+    }
+
+    #[test]
+    fn jump_quirk() {
+        assert_eq!(
+            findings(&[0xB3, 0x00]),
+            vec!["200: BNNN with a non-zero X depends on the jump quirk"]
+        );
+    }
+
+    #[test]
+    fn jump_outside_program() {
+        assert_eq!(
+            findings(&[0x13, 0x00]),
+            vec!["200: jumps outside the loaded program to 300"]
+        );
+    }
+
+    #[test]
+    fn jump_into_sprite_data() {
+        // I = 206, draw a row from it, then jump to it
+        let found = findings(&[0xA2, 0x06, 0xD0, 0x11, 0x12, 0x06, 0x80, 0x08]);
+        assert!(found.contains(&String::from("204: jumps into sprite data at 206")));
+    }
+
+    #[test]
+    fn runs_past_end() {
+        assert_eq!(
+            findings(&[0x60, 0x01]),
+            vec!["200: execution runs past the end of the ROM"]
+        );
+    }
+
+    #[test]
+    fn return_without_call() {
+        assert_eq!(
+            findings(&[0x00, 0xEE]),
+            vec!["200: RET with an empty stack"]
+        );
+    }
+
+    #[test]
+    fn subroutine_never_returns() {
+        // call 204, loop; 204 loops forever
+        assert_eq!(
+            findings(&[0x22, 0x04, 0x12, 0x02, 0x12, 0x04]),
+            vec!["204: subroutine never returns"]
+        );
+    }
+
+    #[test]
+    fn recursion() {
+        // call 204, loop; 204 calls itself and returns
+        assert_eq!(
+            findings(&[0x22, 0x04, 0x12, 0x02, 0x22, 0x04, 0x00, 0xEE]),
+            vec!["204: recursive subroutine may overflow the stack"]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // call 204, loop, then 13 subroutines that each call the next
+        let mut rom = vec![0x22, 0x04, 0x12, 0x02];
+        for depth in 0..13u16 {
+            let address = 0x204 + depth * 4;
+            match depth {
+                12 => rom.extend_from_slice(&[0x00, 0xEE, 0x00, 0xEE]),
+                _ => {
+                    let call = 0x2000 | (address + 4);
+                    rom.extend_from_slice(&call.to_be_bytes());
+                    rom.extend_from_slice(&[0x00, 0xEE]);
+                }
+            }
+        }
+        assert_eq!(
+            findings(&rom),
+            vec!["200: subroutines nest 13 deep, more than the 12 levels of the stack"]
+        );
+    }
+
+    #[test]
+    fn draw_before_index() {
+        assert_eq!(
+            findings(&[0xD0, 0x11, 0x12, 0x02]),
+            vec!["200: DRW before I is loaded"]
+        );
+    }
+
+    #[test]
+    fn draw_from_uninitialised_memory() {
+        assert_eq!(
+            findings(&[0xA3, 0x00, 0xD0, 0x11, 0x12, 0x04]),
+            vec!["202: DRW reads uninitialised memory at 300"]
+        );
+        // Written by FX33 first, so fine
+        assert!(findings(&[0xA3, 0x00, 0xF0, 0x33, 0xD0, 0x13, 0x12, 0x06]).is_empty());
+    }
+
+    #[test]
+    fn index_after_memory_votes_super_chip() {
+        // I = 300, store V0, load V0 without reloading I
+        let report = lint_rom(&[0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x65, 0x12, 0x06]);
+        let found: Vec<_> = report.findings.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            found,
+            vec!["204: uses I after 202 without reloading it, which depends on the memory quirk"]
+        );
+        assert_eq!(report.preset, QuirksPreset::SuperChip);
+    }
+}
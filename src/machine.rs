@@ -0,0 +1,389 @@
+use crate::coverage::Coverage;
+use crate::cpu::Cpu;
+use crate::detect;
+use crate::error::Chip8Error;
+use crate::graphics::Graphics;
+use crate::input::Input;
+use crate::instruction::Instruction;
+use crate::mmu::{Mmu, MAX_ROM_SIZE};
+use crate::movie;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, SeededRandom};
+use crate::scheduler::{Scheduler, DEFAULT_INSTRUCTIONS_PER_FRAME};
+use crate::snapshot::Snapshot;
+
+/// Configures a `Machine`. Anything left unset is worked out from the ROM or
+/// falls back to a default.
+#[derive(Default)]
+pub struct MachineBuilder {
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    rom: Vec<u8>,
+    seed: Option<u64>,
+    random: Option<Box<dyn RandomSource>>,
+    instructions_per_frame: Option<u32>,
+    vip_timing: bool,
+}
+
+impl MachineBuilder {
+    pub fn new() -> MachineBuilder {
+        Self::default()
+    }
+
+    /// Detected from the ROM when not set.
+    pub fn platform(mut self, platform: Platform) -> MachineBuilder {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// The platform's defaults when not set, or the detected quirks when the
+    /// platform isn't set either.
+    pub fn quirks(mut self, quirks: Quirks) -> MachineBuilder {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    pub fn rom(mut self, rom: &[u8]) -> MachineBuilder {
+        self.rom = rom.to_vec();
+        self
+    }
+
+    /// Seeds the default random source. A random seed is picked when not set.
+    pub fn seed(mut self, seed: u64) -> MachineBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Replaces the seeded random source, making `seed` irrelevant.
+    pub fn random(mut self, random: Box<dyn RandomSource>) -> MachineBuilder {
+        self.random = Some(random);
+        self
+    }
+
+    pub fn instructions_per_frame(mut self, instructions_per_frame: u32) -> MachineBuilder {
+        self.instructions_per_frame = Some(instructions_per_frame);
+        self
+    }
+
+    pub fn vip_timing(mut self, enabled: bool) -> MachineBuilder {
+        self.vip_timing = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Machine, Chip8Error> {
+        let mut mmu = Mmu::new();
+        load_rom(&mut mmu, &self.rom)?;
+
+        let (platform, quirks) = match (self.platform, self.quirks) {
+            (Some(platform), Some(quirks)) => (platform, quirks),
+            (Some(platform), None) => (platform, platform.default_quirks().quirks()),
+            (None, quirks) => {
+                let detection = detect::detect(&mmu);
+                (detection.platform, quirks.unwrap_or(detection.quirks))
+            }
+        };
+
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.set_random(match self.random {
+            Some(random) => random,
+            None => Box::new(SeededRandom::new(self.seed.unwrap_or_else(rand::random))),
+        });
+
+        let mut scheduler = Scheduler::new(
+            self.instructions_per_frame
+                .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME),
+        );
+        scheduler.set_vip_timing(self.vip_timing);
+
+        Ok(Machine {
+            platform,
+            scheduler,
+            cpu,
+            mmu,
+            graphics: Graphics::with_planes(platform.planes()),
+            input: Input::new(),
+            coverage: Coverage::new(),
+        })
+    }
+}
+
+/// A complete CHIP-8 machine: processor, memory, display, keypad, timers and
+/// random source, run in 60 Hz frames. It is what the frontend drives, and
+/// can be embedded anywhere else a ROM needs running.
+#[derive(Clone)]
+pub struct Machine {
+    platform: Platform,
+    scheduler: Scheduler,
+    cpu: Cpu,
+    mmu: Mmu,
+    graphics: Graphics,
+    input: Input,
+    coverage: Coverage,
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::new()
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn mmu(&self) -> &Mmu {
+        &self.mmu
+    }
+
+    pub fn graphics(&self) -> &Graphics {
+        &self.graphics
+    }
+
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.mmu
+    }
+
+    pub fn graphics_mut(&mut self) -> &mut Graphics {
+        &mut self.graphics
+    }
+
+    pub fn input_mut(&mut self) -> &mut Input {
+        &mut self.input
+    }
+
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    /// Runs a single instruction. Timers only tick at the end of a frame.
+    pub fn step(&mut self) -> Instruction {
+        let pc = self.cpu.pc();
+        let instruction = self
+            .cpu
+            .step(&mut self.mmu, &mut self.graphics, &self.input);
+        self.coverage.record(pc, &instruction, self.cpu.pc());
+        instruction
+    }
+
+    /// Runs a frame of instructions and ticks the timers.
+    pub fn run_frame(&mut self) {
+        self.scheduler.run_frame(
+            &mut self.cpu,
+            &mut self.mmu,
+            &mut self.graphics,
+            &self.input,
+            &mut self.coverage,
+        );
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.input.set_key_pressed(key & 0xF, pressed);
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.set_key(key, true);
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.set_key(key, false);
+    }
+
+    /// Takes the reason the program stopped, or else a problem the machine
+    /// ran into without stopping, such as its random source failing.
+    pub fn take_error(&mut self) -> Option<Chip8Error> {
+        self.cpu
+            .take_error()
+            .or_else(|| self.cpu.take_random_error())
+    }
+
+    /// Whether the CHIP-8 sound timer is running. An emulated VIP board
+    /// drives its own beeper, which this doesn't cover.
+    pub fn sound(&self) -> bool {
+        self.cpu.sound_timer() > 0
+    }
+
+    /// The plane bits of every pixel, row by row, at the display's current
+    /// width and height.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let (width, height) = (self.graphics.width(), self.graphics.height());
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.graphics.pixel(x, y))
+            .collect()
+    }
+
+    /// SHA-1 of everything that affects what happens next, for checking two
+    /// runs stayed in step.
+    pub fn state_hash(&self) -> String {
+        movie::state_hash(&self.cpu, &self.mmu, &self.graphics)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(&self.scheduler, &self.cpu, &self.mmu, &self.graphics)
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Chip8Error> {
+        snapshot.restore(
+            &mut self.scheduler,
+            &mut self.cpu,
+            &mut self.mmu,
+            &mut self.graphics,
+        )
+    }
+
+    /// Runs `frames` frames on a throwaway copy of the future and returns its
+    /// display. The machine, including coverage and the heatmap, is left as
+    /// it was.
+    pub fn run_ahead(&mut self, frames: u32) -> Result<Graphics, Chip8Error> {
+        let present = self.snapshot();
+        let coverage = std::mem::take(&mut self.coverage);
+        let heatmap = std::mem::take(self.mmu.heatmap_mut());
+        for _ in 0..frames {
+            self.run_frame();
+        }
+        let future = self.graphics.clone();
+        self.restore(&present)?;
+        self.coverage = coverage;
+        *self.mmu.heatmap_mut() = heatmap;
+        Ok(future)
+    }
+
+    /// Swaps in a new ROM and starts it from scratch, keeping the platform,
+    /// quirks, timing and random source.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let mut mmu = Mmu::new();
+        load_rom(&mut mmu, rom)?;
+        self.mmu = mmu;
+        self.cpu.reset();
+        self.graphics = Graphics::with_planes(self.platform.planes());
+        self.coverage = Coverage::new();
+
+        let vip_timing = self.scheduler.vip_timing();
+        self.scheduler = Scheduler::new(self.scheduler.instructions_per_frame());
+        self.scheduler.set_vip_timing(vip_timing);
+        Ok(())
+    }
+}
+
+fn load_rom(mmu: &mut Mmu, rom: &[u8]) -> Result<(), Chip8Error> {
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(Chip8Error::new(&format!(
+            "ROM is {} bytes, at most {} fit in memory",
+            rom.len(),
+            MAX_ROM_SIZE
+        )));
+    }
+    mmu.load_rom(rom.to_vec());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Register;
+
+    #[test]
+    fn builder_detects_platform_unless_given() {
+        // 00FF switches to SUPER-CHIP high resolution
+        let rom = [0x00, 0xFF, 0x12, 0x02];
+        let machine = Machine::builder().rom(&rom).seed(1).build().unwrap();
+        assert_eq!(machine.platform(), detect::detect(machine.mmu()).platform);
+
+        let machine = Machine::builder()
+            .rom(&rom)
+            .platform(Platform::Chip8)
+            .build()
+            .unwrap();
+        assert_eq!(machine.platform(), Platform::Chip8);
+        assert_eq!(
+            machine.cpu().quirks(),
+            Platform::Chip8.default_quirks().quirks()
+        );
+
+        assert!(Machine::builder()
+            .rom(&vec![0; MAX_ROM_SIZE + 1])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn same_seed_runs_in_step() {
+        // V0 = random, store it at I, I += 1, repeat
+        let rom = [0xA3, 0x00, 0xC0, 0xFF, 0xF0, 0x55, 0x12, 0x02];
+        let run = |seed| {
+            let mut machine = Machine::builder()
+                .rom(&rom)
+                .platform(Platform::Chip8)
+                .seed(seed)
+                .build()
+                .unwrap();
+            for _ in 0..10 {
+                machine.run_frame();
+            }
+            machine.state_hash()
+        };
+        assert_eq!(run(5), run(5));
+        assert_ne!(run(5), run(6));
+    }
+
+    #[test]
+    fn keys_reach_the_program() {
+        // V0 = 1 while key 5 is down: skip unless key V1 down, V0 = 1, loop
+        let mut machine = Machine::builder()
+            .rom(&[0x61, 0x05, 0xE1, 0xA1, 0x60, 0x01, 0x12, 0x02])
+            .platform(Platform::Chip8)
+            .build()
+            .unwrap();
+        machine.run_frame();
+        assert_eq!(machine.cpu().reg(Register::V0), 0);
+        machine.press_key(5);
+        machine.run_frame();
+        assert_eq!(machine.cpu().reg(Register::V0), 1);
+    }
+
+    #[test]
+    fn halting_error_is_reported_once() {
+        // 00EE with nothing on the stack
+        let mut machine = Machine::builder()
+            .rom(&[0x00, 0xEE])
+            .platform(Platform::Chip8)
+            .build()
+            .unwrap();
+        machine.run_frame();
+        assert!(machine.cpu().is_halted());
+        assert!(machine.take_error().is_some());
+        assert!(machine.take_error().is_none());
+    }
+
+    #[test]
+    fn run_ahead_leaves_observers_alone() {
+        // V0 += 1, store it at I, repeat
+        let mut machine = Machine::builder()
+            .rom(&[0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x02])
+            .seed(1)
+            .build()
+            .unwrap();
+        machine.step();
+        let heatmap = machine.mmu().heatmap().color(0x300);
+        let hash = machine.state_hash();
+
+        let future = machine.run_ahead(2).unwrap();
+        assert_eq!(future.width(), 64);
+        assert_eq!(machine.state_hash(), hash);
+        assert_eq!(machine.mmu().heatmap().color(0x300), heatmap);
+        assert_eq!(machine.mmu().heatmap().last_pc(0x300), None);
+        assert_eq!(machine.coverage().hits(0x202), 0);
+    }
+}
@@ -1,41 +1,33 @@
-use std::{error::Error, str::FromStr};
+use std::error::Error;
 
-use crate::graphics::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip8::audio::SampleSource;
+use chip8::error::Chip8Error;
+use chip8::{
+    audio, detect, graphics, lint, machine, mmu, movie, postprocess, random, romdb, scheduler,
+    snapshot, symbols, vip,
+};
 
-pub mod cpu;
-pub mod graphics;
-pub mod input;
-pub mod instruction;
-pub mod mmu;
+use crate::emulator::Command;
 
-#[derive(Debug)]
-struct Chip8Error {
-    message: String,
-}
-impl Chip8Error {
-    pub fn new(message: &str) -> Chip8Error {
-        Chip8Error {
-            message: String::from_str(message).unwrap(),
-        }
-    }
-}
+mod debugger;
+mod emulator;
+mod keymap;
+mod options;
+mod sdl_audio;
 
-impl Error for Chip8Error {}
-impl std::fmt::Display for Chip8Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
+/// Width the display is shown at in the debugger, whatever its resolution.
+const DISPLAY_SIZE: f32 = 512.0;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = std::vec::Vec::from_iter(std::env::args());
-    let rom_path = match args.get(1) {
-        Some(rom_path) => rom_path,
-        None => {
-            println!("Rom path is required");
-            return Err(Box::new(Chip8Error::new("Rom path is required")));
+    let options = match options::Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return Err(Box::new(e));
         }
     };
+    let rom_path = &options.rom_path;
 
     let rom = match std::fs::read(rom_path) {
         Ok(data) => data,
@@ -44,11 +36,199 @@ fn main() -> Result<(), Box<dyn Error>> {
             return Err(Box::new(Chip8Error::new("Failed to read rom")));
         }
     };
+    let symbols = match &options.symbols_path {
+        Some(path) => Some(symbols::SymbolMap::load(path)?),
+        None => None,
+    };
+    if rom.len() > mmu::MAX_ROM_SIZE {
+        println!(
+            "ROM is {} bytes, at most {} fit in memory",
+            rom.len(),
+            mmu::MAX_ROM_SIZE
+        );
+        return Err(Box::new(Chip8Error::new("ROM too large")));
+    }
     println!("Loaded ROM: {}", rom_path);
-    let mut cpu = cpu::Cpu::new();
+
+    let mut database = romdb::RomDatabase::bundled();
+    if let Some(path) = &options.rom_db_path {
+        database.extend(romdb::RomDatabase::load(path)?);
+    }
+    let info = database.lookup(&rom).cloned().unwrap_or_default();
+    if let Some(title) = &info.title {
+        match &info.author {
+            Some(author) => println!("Recognised {} by {}", title, author),
+            None => println!("Recognised {}", title),
+        }
+    }
+
+    let rom_sha1 = romdb::sha1_hex(&rom);
     let mut mmu = mmu::Mmu::new();
 
-    mmu.load_rom(rom);
+    let vip = match &options.vip_interpreter_path {
+        Some(path) => Some(vip::Vip::new(&std::fs::read(path)?, &rom)?),
+        None => None,
+    };
+    mmu.load_rom(rom.clone());
+
+    if options.lint {
+        let report = lint::lint(&mmu);
+        for finding in &report.findings {
+            println!("{}", finding);
+        }
+        println!("Suggested quirks: {}", report.preset);
+        return Ok(());
+    }
+
+    let playback = match options
+        .verify_movie_path
+        .as_ref()
+        .or(options.play_movie_path.as_ref())
+    {
+        Some(path) => Some(movie::Movie::load(path)?),
+        None => None,
+    };
+    if let Some(movie) = &playback {
+        if movie.rom != rom_sha1 {
+            println!("Warning: the movie was recorded with a different ROM");
+        }
+    }
+
+    let (platform, quirks) = if let Some(movie) = &playback {
+        (movie.platform, movie.quirks)
+    } else {
+        match (info.platform, info.quirks) {
+            (Some(platform), Some(preset)) => (platform, preset.quirks()),
+            (Some(platform), None) => (platform, platform.default_quirks().quirks()),
+            (None, preset) => {
+                let detection = detect::detect(&mmu);
+                println!(
+                    "Detected {} ({:.0}% confidence)",
+                    detection.platform,
+                    detection.confidence * 100.0
+                );
+                for reason in &detection.reasons {
+                    println!("  {}", reason);
+                }
+                (
+                    detection.platform,
+                    preset.map_or(detection.quirks, |preset| preset.quirks()),
+                )
+            }
+        }
+    };
+    let seed = match &playback {
+        Some(movie) => movie.seed,
+        None => options.seed.unwrap_or_else(rand::random),
+    };
+    let mut random = random::from_spec(&options.random, seed)?;
+    if let Some(path) = &options.record_random_path {
+        random = Box::new(random::RecordingRandom::create(path, random)?);
+    }
+
+    let (instructions_per_frame, vip_timing) = match &playback {
+        Some(movie) => (movie.instructions_per_frame, movie.vip_timing),
+        None => (
+            options
+                .instructions_per_frame
+                .or(info.tick_rate)
+                .unwrap_or(scheduler::DEFAULT_INSTRUCTIONS_PER_FRAME),
+            options.vip_timing,
+        ),
+    };
+    let machine = machine::Machine::builder()
+        .platform(platform)
+        .quirks(quirks)
+        .rom(&rom)
+        .random(random)
+        .instructions_per_frame(instructions_per_frame)
+        .vip_timing(vip_timing)
+        .build()?;
+
+    let palette = match &options.palette {
+        Some(palette) => palette.clone(),
+        None if !info.colors.is_empty() => postprocess::Palette::new(info.colors.clone())?,
+        None => postprocess::Palette::default(),
+    };
+    let postprocessor = postprocess::PostProcessor::new(palette, options.effects);
+    let mut keymap = keymap::Keymap::new(options.keymap);
+    keymap.apply_overrides(&info.keys)?;
+    keymap.apply_overrides(&options.keys)?;
+    keymap.set_axis_threshold(options.axis_threshold);
+    let mut show_uncovered = true;
+    let mut clicked_key = None;
+
+    let recording_movie = options.record_movie_path.as_ref().map(|_| {
+        movie::Movie::new(
+            rom_sha1.clone(),
+            platform,
+            quirks,
+            instructions_per_frame,
+            vip_timing,
+            seed,
+        )
+    });
+    let mut emulator = emulator::Emulator::new(&options, machine, vip, postprocessor);
+    emulator.playback = playback;
+    emulator.recording_movie = recording_movie;
+    emulator.run_ahead = options.run_ahead.or(info.run_ahead).unwrap_or(0);
+
+    let headless_frames = match (&options.verify_movie_path, &emulator.playback) {
+        (Some(_), Some(movie)) => Some(movie.frames),
+        _ => options.headless_frames,
+    };
+    if let Some(frames) = headless_frames {
+        let mut wav = match &options.wav_path {
+            Some(path) => Some(audio::WavWriter::create(path)?),
+            None => None,
+        };
+        let mut beeper = audio::Beeper::new(options.beeper, audio::SAMPLE_RATE);
+        let mut samples = vec![0.0; (audio::SAMPLE_RATE / scheduler::FRAME_RATE) as usize];
+
+        for _ in 0..frames {
+            emulator.run_frame()?;
+            emulator.record_frame()?;
+            if let Some(wav) = &mut wav {
+                beeper.set_on(emulator.sound());
+                beeper.set_pattern(
+                    emulator.machine.cpu().audio_pattern(),
+                    emulator.machine.cpu().pitch(),
+                );
+                beeper.fill(&mut samples);
+                wav.write(&samples)?;
+            }
+        }
+        if let Some(wav) = wav {
+            wav.finish()?;
+        }
+        emulator.finish_recording()?;
+        if let Some(path) = &options.screenshot_path {
+            emulator.screenshot(path)?;
+        }
+        write_coverage(&options, &emulator, symbols.as_ref());
+
+        if let Some(movie) = emulator.recording_movie.take() {
+            save_movie(
+                &options,
+                movie,
+                emulator.frames_run,
+                emulator.machine.state_hash(),
+            )?;
+        }
+        if options.verify_movie_path.is_some() && emulator.movie_verified == Some(false) {
+            return Err(Box::new(Chip8Error::new("Movie verification failed")));
+        }
+        return Ok(());
+    }
+
+    // Rewind only covers the CHIP-8 machine, not the emulated VIP.
+    if emulator.vip.is_none() && options.rewind_budget > 0 {
+        emulator.rewind = Some(snapshot::Rewind::new(
+            options.rewind_budget,
+            options.rewind_depth,
+            options.rewind_interval,
+        ));
+    }
 
     let sdl_context = sdl2::init()?;
     let video = sdl_context.video()?;
@@ -66,6 +246,10 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let _gl_context = window.gl_create_context()?;
     gl::load_with(|s| video.gl_get_proc_address(s) as _);
+    // Emulation keeps its own time, so the UI can wait for the display.
+    if let Err(e) = video.gl_set_swap_interval(sdl2::video::SwapInterval::VSync) {
+        println!("Failed to enable vsync: {}", e);
+    }
 
     let mut imgui = imgui::Context::create();
     imgui.set_ini_filename(None);
@@ -74,35 +258,61 @@ fn main() -> Result<(), Box<dyn Error>> {
     let renderer =
         imgui_opengl_renderer::Renderer::new(&mut imgui, |s| video.gl_get_proc_address(s) as _);
 
-    let mut graphics = graphics::Graphics::new();
+    let audio_subsystem = sdl_context.audio()?;
+    let mut beeper = audio::Beeper::new(options.beeper, audio::SAMPLE_RATE);
+    let mut audio_sync = None;
+    let mut beeper_device = None;
+    match options.audio_sync_latency {
+        Some(latency) => {
+            audio_sync = Some(sdl_audio::AudioSync::open(
+                &audio_subsystem,
+                std::time::Duration::from_millis(latency),
+            )?);
+        }
+        None => {
+            let device = audio_subsystem.open_playback(
+                None,
+                &sdl2::audio::AudioSpecDesired {
+                    freq: Some(audio::SAMPLE_RATE as i32),
+                    channels: Some(1),
+                    samples: Some(512),
+                },
+                |spec| sdl_audio::Speaker(audio::Beeper::new(options.beeper, spec.freq as u32)),
+            )?;
+            device.resume();
+            beeper_device = Some(device);
+        }
+    }
+
+    let game_controller = sdl_context.game_controller()?;
+    let mut controllers = Vec::new();
+    let mut events = sdl_context.event_pump()?;
 
+    let palette = emulator.postprocessor.palette().clone();
+    let effects = !options.effects.is_none();
+    let pacing = match audio_sync {
+        Some(_) => emulator::Pacing::External,
+        None => emulator::Pacing::RealTime,
+    };
+    let (commands, published, emulation) = emulator.spawn(pacing);
+    let mut state = published.recv()?;
+    // Whether a `RunFrames` sent for audio sync is still being answered.
+    let mut awaiting_frames = false;
+
+    let mut rgba = Vec::new();
     let texture = unsafe {
         use gl::types::GLuint;
         let mut gl_texture: GLuint = 0;
 
-        let data = graphics.to_rgba();
-
         gl::GenTextures(1, std::ptr::addr_of_mut!(gl_texture));
         gl::BindTexture(gl::TEXTURE_2D, gl_texture);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
 
-        gl::TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::RGBA as i32,
-            graphics::DISPLAY_WIDTH as i32,
-            graphics::DISPLAY_HEIGHT as i32,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            data.as_ptr() as *const std::ffi::c_void,
-        );
+        upload_display(&state.graphics, &palette, &mut rgba, true);
         gl_texture
     };
-
-    let mut events = sdl_context.event_pump()?;
+    let mut texture_resolution = state.graphics.resolution();
 
     'quit: loop {
         for event in events.poll_iter() {
@@ -112,29 +322,170 @@ fn main() -> Result<(), Box<dyn Error>> {
                 continue;
             }
 
-            match event {
+            let command = match event {
                 sdl2::event::Event::Quit { .. } => break 'quit,
-                _ => {}
-            }
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F12),
+                    repeat: false,
+                    ..
+                } => Command::Screenshot,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F11),
+                    repeat: false,
+                    ..
+                } => Command::ToggleRecording,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F5),
+                    repeat: false,
+                    ..
+                } => Command::TogglePause,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F6),
+                    ..
+                } => Command::Advance,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F7),
+                    repeat: false,
+                    ..
+                } => Command::ToggleSlowMotion,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F8),
+                    repeat: false,
+                    ..
+                } => Command::CycleFastForward,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::Tab),
+                    ..
+                } => Command::SetFastForward(true),
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(sdl2::keyboard::Keycode::Tab),
+                    ..
+                } => Command::SetFastForward(false),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::Backspace),
+                    ..
+                } => Command::SetRewinding(true),
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(sdl2::keyboard::Keycode::Backspace),
+                    ..
+                } => Command::SetRewinding(false),
+                sdl2::event::Event::DropFile { filename, .. } => Command::Load(filename),
+                sdl2::event::Event::KeyDown {
+                    keycode,
+                    scancode,
+                    repeat: false,
+                    ..
+                } => {
+                    send_keys(&commands, keymap.handle_key(keycode, scancode, true));
+                    continue;
+                }
+                sdl2::event::Event::KeyUp {
+                    keycode, scancode, ..
+                } => {
+                    send_keys(&commands, keymap.handle_key(keycode, scancode, false));
+                    continue;
+                }
+                sdl2::event::Event::ControllerButtonDown { button, .. } => {
+                    send_keys(&commands, keymap.handle_button(button, true));
+                    continue;
+                }
+                sdl2::event::Event::ControllerButtonUp { button, .. } => {
+                    send_keys(&commands, keymap.handle_button(button, false));
+                    continue;
+                }
+                sdl2::event::Event::ControllerAxisMotion { axis, value, .. } => {
+                    send_keys(&commands, keymap.handle_axis(axis, value));
+                    continue;
+                }
+                // SDL also sends these for controllers connected at start-up.
+                sdl2::event::Event::ControllerDeviceAdded { which, .. } => {
+                    match game_controller.open(which) {
+                        Ok(controller) => {
+                            println!("Connected {}", controller.name());
+                            controllers.push(controller);
+                        }
+                        Err(e) => println!("Failed to open controller: {}", e),
+                    }
+                    continue;
+                }
+                sdl2::event::Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.retain(|controller| controller.instance_id() != which);
+                    send_keys(&commands, keymap.release_controllers());
+                    continue;
+                }
+                _ => continue,
+            };
+            // A send only fails once the thread has stopped, which the
+            // frame channel reports.
+            let _ = commands.send(command);
         }
 
-        unsafe {
-            let data = graphics.to_rgba();
+        // Catch up on everything published since the last redraw. Displays
+        // that are skipped pass their dirty rows on to the next.
+        let mut received = false;
+        let mut processed = None;
+        loop {
+            match published.try_recv() {
+                Ok(mut next) => {
+                    if received {
+                        next.graphics.add_dirty_rows(&state.graphics);
+                    }
+                    if let Some(audio_sync) = &mut audio_sync {
+                        let muted = next.speed != 1.0
+                            && options.off_speed_audio == audio::OffSpeedAudio::Mute;
+                        beeper.set_pitch(next.speed);
+                        beeper.set_pattern(next.cpu.audio_pattern(), next.cpu.pitch());
+                        for &on in &next.sounds {
+                            beeper.set_on(on && !muted);
+                            audio_sync.push_frame(&mut beeper)?;
+                        }
+                    }
+                    awaiting_frames &= !next.paced;
+                    if next.processed.is_some() {
+                        processed = next.processed.take();
+                    }
+                    state = next;
+                    received = true;
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => break 'quit,
+            }
+        }
 
-            gl::BindTexture(gl::TEXTURE_2D, texture);
-            gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        if let Some(audio_sync) = &mut audio_sync {
+            if !awaiting_frames {
+                audio_sync.set_speed(state.speed);
+                let _ = commands.send(Command::RunFrames(audio_sync.frames_due()));
+                awaiting_frames = true;
+            }
+        }
+        if let Some(device) = &mut beeper_device {
+            let muted = state.speed != 1.0 && options.off_speed_audio == audio::OffSpeedAudio::Mute;
+            let mut speaker = device.lock();
+            speaker.0.set_pitch(state.speed);
+            speaker.0.set_on(state.sound && !muted && !state.paused);
+            speaker
+                .0
+                .set_pattern(state.cpu.audio_pattern(), state.cpu.pitch());
+        }
 
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                gl::RGBA as i32,
-                graphics::DISPLAY_WIDTH as i32,
-                graphics::DISPLAY_HEIGHT as i32,
-                0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                data.as_ptr() as *const std::ffi::c_void,
-            );
+        if let Some(processed) = &processed {
+            processed.render(&mut rgba);
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                upload_processed(processed, &rgba);
+            }
+        } else if received && !effects {
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                upload_display(
+                    &state.graphics,
+                    &palette,
+                    &mut rgba,
+                    state.graphics.resolution() != texture_resolution,
+                );
+            }
+            texture_resolution = state.graphics.resolution();
         }
 
         imgui_sdl2.prepare_frame(imgui.io_mut(), &window, &events.mouse_state());
@@ -144,25 +495,183 @@ fn main() -> Result<(), Box<dyn Error>> {
         ui.window("Test").build(|| {
             let texture_id = imgui::TextureId::new(texture as usize);
             if ui.button("Step") {
-                cpu.step(&mut mmu, &mut graphics);
+                let _ = commands.send(Command::Step);
+            }
+            ui.same_line();
+            let label = if state.paused { "Resume" } else { "Pause" };
+            if ui.button(label) {
+                let _ = commands.send(Command::TogglePause);
+            }
+            ui.same_line();
+            if ui.button("Advance") {
+                let _ = commands.send(Command::Advance);
+            }
+            if let Some(indicator) = &state.indicator {
+                ui.same_line();
+                ui.text_colored([1.0, 0.8, 0.2, 1.0], indicator);
+            }
+            ui.text("F5 pause, F6 advance, hold Tab to fast-forward, F8 speed, F7 slow motion");
+            if let Some((frames, memory)) = state.rewind {
+                ui.text(format!(
+                    "{}Rewind: {:.1}s, {} KiB (hold Backspace)",
+                    if state.rewinding { "<< " } else { "" },
+                    frames as f32 / scheduler::FRAME_RATE as f32,
+                    memory / 1024
+                ));
             }
             imgui::Image::new(
                 texture_id,
-                [(DISPLAY_WIDTH as f32) * 4.0, (DISPLAY_HEIGHT as f32) * 4.0],
+                [
+                    DISPLAY_SIZE,
+                    DISPLAY_SIZE * state.graphics.height() as f32 / state.graphics.width() as f32,
+                ],
             )
-            .build(&ui)
+            .build(ui)
         });
+        debugger::disassembly_window(
+            ui,
+            &state.cpu,
+            &state.mmu,
+            &state.coverage,
+            &mut show_uncovered,
+        );
+        debugger::heatmap_window(ui, &state.mmu);
+        let clicked = debugger::keypad_window(
+            ui,
+            (!state.vip).then_some(&state.cpu),
+            &state.mmu,
+            &state.input,
+            &keymap,
+            &mut clicked_key,
+        );
+        send_keys(&commands, clicked);
 
         unsafe {
             gl::ClearColor(0.2, 0.2, 0.2, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
 
-        imgui_sdl2.prepare_render(&ui, &window);
+        imgui_sdl2.prepare_render(ui, &window);
         renderer.render(&mut imgui);
 
         window.gl_swap_window();
     }
 
+    let _ = commands.send(Command::Quit);
+    let mut emulator = emulation
+        .join()
+        .map_err(|_| Chip8Error::new("The emulation thread panicked"))??;
+    if let Err(e) = emulator.finish_recording() {
+        println!("{}", e);
+    }
+    write_coverage(&options, &emulator, symbols.as_ref());
+    if let Some(movie) = emulator.recording_movie.take() {
+        save_movie(
+            &options,
+            movie,
+            emulator.frames_run,
+            emulator.machine.state_hash(),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn save_movie(
+    options: &options::Options,
+    mut movie: movie::Movie,
+    frames: u64,
+    hash: String,
+) -> Result<(), Chip8Error> {
+    movie.frames = frames;
+    movie.hash = Some(hash);
+    if let Some(path) = &options.record_movie_path {
+        movie.save(path)?;
+        println!("Saved movie of {} frames to {}", frames, path);
+    }
     Ok(())
 }
+
+/// Uploads the display to the bound texture. Unless `resize` is set only the
+/// dirty rows are rendered and sent.
+unsafe fn upload_display(
+    graphics: &graphics::Graphics,
+    palette: &postprocess::Palette,
+    rgba: &mut Vec<u8>,
+    resize: bool,
+) {
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+
+    if resize || rgba.len() != graphics.width() * graphics.height() * 4 {
+        graphics.write_rgba(palette, rgba);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA as i32,
+            graphics.width() as i32,
+            graphics.height() as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            rgba.as_ptr() as *const std::ffi::c_void,
+        );
+        return;
+    }
+
+    let row_size = graphics.width() * 4;
+    for (y, row) in rgba.chunks_exact_mut(row_size).enumerate() {
+        if !graphics.is_row_dirty(y) {
+            continue;
+        }
+        graphics.write_rgba_row(palette, y, row);
+        gl::TexSubImage2D(
+            gl::TEXTURE_2D,
+            0,
+            0,
+            y as i32,
+            graphics.width() as i32,
+            1,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            row.as_ptr() as *const std::ffi::c_void,
+        );
+    }
+}
+
+/// Uploads a whole post-processed picture to the bound texture.
+unsafe fn upload_processed(postprocessor: &postprocess::PostProcessor, rgba: &[u8]) {
+    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA as i32,
+        postprocessor.output_width() as i32,
+        postprocessor.output_height() as i32,
+        0,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        rgba.as_ptr() as *const std::ffi::c_void,
+    );
+}
+
+fn send_keys(commands: &std::sync::mpsc::Sender<Command>, keys: Vec<(u8, bool)>) {
+    for (key, pressed) in keys {
+        let _ = commands.send(Command::Key(key, pressed));
+    }
+}
+
+fn write_coverage(
+    options: &options::Options,
+    emulator: &emulator::Emulator,
+    symbols: Option<&symbols::SymbolMap>,
+) {
+    if let Some(path) = &options.lcov_path {
+        let machine = &emulator.machine;
+        let lcov = machine
+            .coverage()
+            .to_lcov(machine.mmu(), &emulator.rom_path, symbols);
+        if let Err(e) = std::fs::write(path, lcov) {
+            println!("Failed to write coverage: {}", e);
+        }
+    }
+}
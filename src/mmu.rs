@@ -1,10 +1,29 @@
+use std::cell::{Ref, RefCell};
+
+use crate::error::Chip8Error;
+use crate::heatmap::Heatmap;
+use crate::snapshot::StateReader;
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const PROGRAM_START: u16 = 0x200;
+/// Addresses wrap around the end of memory, so a ROM pointing I or the
+/// program counter past it cannot read outside the array.
+pub const ADDRESS_MASK: u16 = MEMORY_SIZE as u16 - 1;
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - PROGRAM_START as usize;
+
+#[derive(Clone)]
 pub struct Mmu {
-    memory: [u8; 4096],
+    memory: [u8; MEMORY_SIZE],
     stack: [u16; 1024],
     sp: usize,
+    rom_size: usize,
+    heatmap: RefCell<Heatmap>,
 }
 
-const FONT: [u8; 80] = [
+/// The built-in hex font lives at the start of memory.
+pub const FONT_SIZE: usize = 80;
+
+const FONT: [u8; FONT_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -23,52 +42,158 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// The SUPER-CHIP 8x10 font, with XO-CHIP's A to F, follows the small one.
+pub const BIG_FONT_START: u16 = FONT_SIZE as u16;
+pub const BIG_FONT_SIZE: usize = 160;
+
+const BIG_FONT: [u8; BIG_FONT_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Mmu {
     pub fn new() -> Mmu {
         let mut mmu = Mmu {
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             stack: [0; 1024],
             sp: 1024,
+            rom_size: 0,
+            heatmap: RefCell::new(Heatmap::new()),
         };
 
         for (i, &value) in FONT.iter().enumerate() {
             mmu.memory[i] = value;
         }
+        let big_font = BIG_FONT_START as usize;
+        mmu.memory[big_font..big_font + BIG_FONT_SIZE].copy_from_slice(&BIG_FONT);
 
         mmu
     }
 
+    /// Copies `rom` to the program area, dropping anything past `MAX_ROM_SIZE`.
     pub fn load_rom(&mut self, rom: Vec<u8>) {
-        for (i, &element) in rom.iter().enumerate() {
-            self.memory[0x200 + i] = element;
+        for (i, &element) in rom.iter().take(MAX_ROM_SIZE).enumerate() {
+            self.memory[PROGRAM_START as usize + i] = element;
         }
+        self.rom_size = rom.len().min(MAX_ROM_SIZE);
+    }
+
+    /// One past the last address written by `load_rom`.
+    pub fn rom_end(&self) -> u16 {
+        PROGRAM_START + self.rom_size as u16
     }
 
-    pub fn push_stack(&mut self, value: u16) {
+    pub fn push_stack(&mut self, value: u16) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::new("Stack overflow"));
+        }
         self.sp -= 1;
         self.stack[self.sp] = value;
+        Ok(())
     }
 
-    pub fn pop_stack(&mut self) -> u16 {
+    pub fn pop_stack(&mut self) -> Result<u16, Chip8Error> {
+        if self.sp == self.stack.len() {
+            return Err(Chip8Error::new("Return with an empty stack"));
+        }
         let value = self.stack[self.sp];
         self.sp += 1;
-        value
+        Ok(value)
+    }
+
+    /// Appends memory and the stack to `out`. The heatmap is left out.
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&(self.rom_size as u16).to_le_bytes());
+        out.extend_from_slice(&(self.sp as u16).to_le_bytes());
+        for value in self.stack() {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.memory.copy_from_slice(reader.bytes(MEMORY_SIZE)?);
+        self.rom_size = reader.u16()? as usize;
+        let sp = reader.u16()? as usize;
+        if sp > self.stack.len() {
+            return Err(Chip8Error::new("Snapshot stack pointer out of range"));
+        }
+        self.sp = sp;
+        for i in sp..self.stack.len() {
+            self.stack[i] = reader.u16()?;
+        }
+        Ok(())
+    }
+
+    /// Return addresses currently on the stack, innermost first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[self.sp..]
     }
 
     pub fn write8(&mut self, address: u16, value: u8) {
+        let address = address & ADDRESS_MASK;
+        self.heatmap.get_mut().write(address);
         self.memory[address as usize] = value;
     }
 
     pub fn read8(&self, address: u16) -> u8 {
+        let address = address & ADDRESS_MASK;
+        self.heatmap.borrow_mut().read(address);
         self.memory[address as usize]
     }
 
+    /// Reads the instruction at `address` and attributes later accesses to it.
+    pub fn fetch16(&self, address: u16) -> u16 {
+        let mut heatmap = self.heatmap.borrow_mut();
+        heatmap.set_pc(address & ADDRESS_MASK);
+        heatmap.execute(address & ADDRESS_MASK);
+        heatmap.execute(address.wrapping_add(1) & ADDRESS_MASK);
+        self.peek16(address)
+    }
+
+    /// Reads memory without recording the access, for debuggers and analysis.
+    pub fn peek8(&self, address: u16) -> u8 {
+        self.memory[(address & ADDRESS_MASK) as usize]
+    }
+
+    pub fn peek16(&self, address: u16) -> u16 {
+        ((self.peek8(address) as u16) << 8) | self.peek8(address.wrapping_add(1)) as u16
+    }
+
+    pub fn heatmap(&self) -> Ref<'_, Heatmap> {
+        self.heatmap.borrow()
+    }
+
+    pub fn heatmap_mut(&mut self) -> &mut Heatmap {
+        self.heatmap.get_mut()
+    }
+
     pub fn write16(&mut self, address: u16, value: u16) {
         self.write8(address, ((value & 0xff00) >> 8) as u8);
-        self.write8(address + 1, ((value & 0xff) >> 8) as u8);
+        self.write8(address.wrapping_add(1), ((value & 0xff) >> 8) as u8);
     }
 
     pub fn read16(&self, address: u16) -> u16 {
-        ((self.read8(address) as u16) << 8) | self.read8(address + 1) as u16
+        ((self.read8(address) as u16) << 8) | self.read8(address.wrapping_add(1)) as u16
     }
 }
@@ -0,0 +1,321 @@
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::graphics::Graphics;
+use crate::input::Input;
+use crate::mmu::Mmu;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+/// A recorded session: everything needed to replay it exactly, the keypad
+/// changes with the frame they happened before, and the state it ended in.
+///
+/// Movies are text, with `key = value` settings followed by one
+/// `<frame> <hex key> down|up` line per change.
+#[derive(Clone, Debug)]
+pub struct Movie {
+    /// SHA-1 of the ROM the movie was recorded with.
+    pub rom: String,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub vip_timing: bool,
+    pub seed: u64,
+    pub events: Vec<MovieEvent>,
+    /// Frames run when recording stopped.
+    pub frames: u64,
+    /// `state_hash` when recording stopped.
+    pub hash: Option<String>,
+    keys: [bool; 16],
+    cursor: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MovieEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+impl Movie {
+    pub fn new(
+        rom: String,
+        platform: Platform,
+        quirks: Quirks,
+        instructions_per_frame: u32,
+        vip_timing: bool,
+        seed: u64,
+    ) -> Movie {
+        Movie {
+            rom,
+            platform,
+            quirks,
+            instructions_per_frame,
+            vip_timing,
+            seed,
+            events: vec![],
+            frames: 0,
+            hash: None,
+            keys: [false; 16],
+            cursor: 0,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, Chip8Error> {
+        let mut movie = Movie::new(
+            String::new(),
+            Platform::Chip8,
+            Quirks::default(),
+            0,
+            false,
+            0,
+        );
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid =
+                |what: &str| Chip8Error::new(&format!("Invalid {} on line {}", what, number + 1));
+
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                match key.trim() {
+                    "rom" => movie.rom = String::from(value),
+                    "platform" => {
+                        movie.platform =
+                            Platform::from_name(value).ok_or_else(|| invalid("platform"))?
+                    }
+                    "quirks" => {
+                        movie.quirks = Quirks::parse(value).ok_or_else(|| invalid("quirks"))?
+                    }
+                    "ipf" => {
+                        movie.instructions_per_frame = value.parse().map_err(|_| invalid("ipf"))?
+                    }
+                    "vip_timing" => {
+                        movie.vip_timing = value.parse().map_err(|_| invalid("vip_timing"))?
+                    }
+                    "seed" => movie.seed = value.parse().map_err(|_| invalid("seed"))?,
+                    "frames" => movie.frames = value.parse().map_err(|_| invalid("frames"))?,
+                    "hash" => movie.hash = Some(String::from(value)),
+                    _ => return Err(invalid("setting")),
+                }
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let frame = fields.next().and_then(|frame| frame.parse().ok());
+            let key = fields
+                .next()
+                .and_then(|key| u8::from_str_radix(key, 16).ok())
+                .filter(|&key| key < 16);
+            let pressed = match fields.next() {
+                Some("down") => Some(true),
+                Some("up") => Some(false),
+                _ => None,
+            };
+            match (frame, key, pressed, fields.next()) {
+                (Some(frame), Some(key), Some(pressed), None) => movie.events.push(MovieEvent {
+                    frame,
+                    key,
+                    pressed,
+                }),
+                _ => return Err(invalid("event")),
+            }
+        }
+
+        if movie.instructions_per_frame == 0 {
+            return Err(Chip8Error::new("Movie has no ipf"));
+        }
+        Ok(movie)
+    }
+
+    pub fn load(path: &str) -> Result<Movie, Chip8Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Chip8Error::new(&format!("Failed to read movie {}: {}", path, e)))?;
+        Movie::parse(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Chip8Error> {
+        std::fs::write(path, self.to_string())
+            .map_err(|e| Chip8Error::new(&format!("Failed to write movie {}: {}", path, e)))
+    }
+
+    /// Records any keypad changes since the last call, as happening before
+    /// `frame` runs.
+    pub fn record_input(&mut self, frame: u64, input: &Input) {
+        for key in 0..16 {
+            let pressed = input.key_pressed(key);
+            if pressed != self.keys[key as usize] {
+                self.keys[key as usize] = pressed;
+                self.events.push(MovieEvent {
+                    frame,
+                    key,
+                    pressed,
+                });
+            }
+        }
+    }
+
+    /// Sets the keypad to the movie's state before `frame` runs, replacing
+    /// whatever the player is pressing.
+    pub fn apply_input(&mut self, frame: u64, input: &mut Input) {
+        while let Some(event) = self.events.get(self.cursor) {
+            if event.frame > frame {
+                break;
+            }
+            self.keys[event.key as usize] = event.pressed;
+            self.cursor += 1;
+        }
+        for key in 0..16 {
+            input.set_key_pressed(key, self.keys[key as usize]);
+        }
+    }
+
+    /// Whether every recorded frame has been played.
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.frames && self.cursor >= self.events.len()
+    }
+}
+
+impl std::fmt::Display for Movie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# chip8 input movie")?;
+        writeln!(f, "rom = {}", self.rom)?;
+        writeln!(f, "platform = {}", self.platform.name())?;
+        writeln!(f, "quirks = {}", self.quirks)?;
+        writeln!(f, "ipf = {}", self.instructions_per_frame)?;
+        writeln!(f, "vip_timing = {}", self.vip_timing)?;
+        writeln!(f, "seed = {}", self.seed)?;
+        writeln!(f, "frames = {}", self.frames)?;
+        if let Some(hash) = &self.hash {
+            writeln!(f, "hash = {}", hash)?;
+        }
+        writeln!(f)?;
+        for event in &self.events {
+            let state = if event.pressed { "down" } else { "up" };
+            writeln!(f, "{} {:X} {}", event.frame, event.key, state)?;
+        }
+        Ok(())
+    }
+}
+
+/// SHA-1 of everything that decides what the machine does next: registers,
+/// timers, stack, memory and the display. The heatmap and coverage are left
+/// out, they only observe.
+pub fn state_hash(cpu: &Cpu, mmu: &Mmu, graphics: &Graphics) -> String {
+    let mut state = Vec::new();
+    cpu.save_state(&mut state);
+    mmu.save_state(&mut state);
+    graphics.save_state(&mut state);
+    sha1_smol::Sha1::from(&state).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coverage::Coverage;
+    use crate::scheduler::Scheduler;
+
+    #[test]
+    fn text_round_trip() {
+        let mut movie = Movie::new(
+            String::from("0123456789abcdef0123456789abcdef01234567"),
+            Platform::XoChip,
+            Platform::XoChip.default_quirks().quirks(),
+            1000,
+            true,
+            42,
+        );
+        movie.events = vec![
+            MovieEvent {
+                frame: 3,
+                key: 0xA,
+                pressed: true,
+            },
+            MovieEvent {
+                frame: 10,
+                key: 0xA,
+                pressed: false,
+            },
+        ];
+        movie.frames = 60;
+        movie.hash = Some(String::from("feedface"));
+
+        let text = movie.to_string();
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed.rom, movie.rom);
+        assert_eq!(parsed.platform, movie.platform);
+        assert_eq!(parsed.quirks, movie.quirks);
+        assert_eq!(parsed.instructions_per_frame, 1000);
+        assert!(parsed.vip_timing);
+        assert_eq!(parsed.seed, 42);
+        assert_eq!(parsed.events, movie.events);
+        assert_eq!(parsed.frames, 60);
+        assert_eq!(parsed.hash, movie.hash);
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn rejects_malformed_events() {
+        let header = "ipf = 10\n";
+        assert!(Movie::parse(&format!("{}3 A down", header)).is_ok());
+        assert!(Movie::parse(&format!("{}3 G down", header)).is_err());
+        assert!(Movie::parse(&format!("{}3 A sideways", header)).is_err());
+        assert!(Movie::parse("3 A down").is_err());
+    }
+
+    /// Rolls a random number each pass and draws its digit whenever key 0
+    /// is held, so the final state depends on both the seed and the input.
+    const ROM: [u8; 14] = [
+        0xC0, 0xFF, // V0 = random
+        0xE1, 0x9E, // skip if key V1 is down
+        0x12, 0x00, // jump 200
+        0x72, 0x01, // V2 += 1
+        0xF0, 0x29, // I = digit V0
+        0xD1, 0x25, // draw at V1, V2
+        0x12, 0x00, // jump 200
+    ];
+
+    /// Runs `frames` frames of `ROM` seeded with `seed`, letting `update`
+    /// drive the keypad before each one, and returns the final state hash.
+    fn run(seed: u64, frames: u64, mut update: impl FnMut(u64, &mut Input)) -> String {
+        let quirks = Platform::Chip8.default_quirks().quirks();
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.seed_rng(seed);
+        let mut mmu = Mmu::new();
+        mmu.load_rom(ROM.to_vec());
+        let mut graphics = Graphics::new();
+        let mut scheduler = Scheduler::new(20);
+        let mut coverage = Coverage::new();
+        let mut input = Input::new();
+        for frame in 0..frames {
+            update(frame, &mut input);
+            scheduler.run_frame(&mut cpu, &mut mmu, &mut graphics, &input, &mut coverage);
+        }
+        state_hash(&cpu, &mmu, &graphics)
+    }
+
+    #[test]
+    fn replay_reaches_the_recorded_state() {
+        let quirks = Platform::Chip8.default_quirks().quirks();
+        let mut movie = Movie::new(String::new(), Platform::Chip8, quirks, 20, false, 7);
+        let recorded = run(7, 30, |frame, input| {
+            input.set_key_pressed(0, (5..12).contains(&frame) || frame >= 20);
+            movie.record_input(frame, input);
+        });
+
+        let text = movie.to_string();
+        let mut replay = Movie::parse(&text).unwrap();
+        let replayed = run(7, 30, |frame, input| replay.apply_input(frame, input));
+        assert_eq!(replayed, recorded);
+
+        assert_ne!(run(7, 30, |_, _| {}), recorded);
+        let mut replay = Movie::parse(&text).unwrap();
+        assert_ne!(
+            run(8, 30, |frame, input| replay.apply_input(frame, input)),
+            recorded
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use chip8::audio::{BeeperSettings, OffSpeedAudio, Waveform};
+use chip8::error::Chip8Error;
+use chip8::postprocess::{Effects, Palette};
+use chip8::romdb;
+
+use crate::keymap::{self, KeymapPreset};
+
+pub struct Options {
+    pub rom_path: String,
+    /// Assembler symbol map used to report coverage against source lines.
+    pub symbols_path: Option<String>,
+    /// Where to write the lcov tracefile when the emulator exits.
+    pub lcov_path: Option<String>,
+    /// Print static analysis findings for the ROM and exit.
+    pub lint: bool,
+    /// Local rom database that extends the bundled one.
+    pub rom_db_path: Option<String>,
+    /// Instructions run per 60 Hz frame, overriding the rom database.
+    pub instructions_per_frame: Option<u32>,
+    /// Run this many frames as fast as possible without a window, then exit.
+    pub headless_frames: Option<u64>,
+    /// Time instructions by COSMAC VIP machine cycles instead of a fixed count.
+    pub vip_timing: bool,
+    /// Run the ROM on an emulated COSMAC VIP with this interpreter image.
+    pub vip_interpreter_path: Option<String>,
+    pub beeper: BeeperSettings,
+    /// Capture the beeper to a WAV file during headless runs.
+    pub wav_path: Option<String>,
+    /// Speeds the fast-forward hotkey cycles through.
+    pub fast_forward: Vec<f32>,
+    pub slow_motion: f32,
+    pub off_speed_audio: OffSpeedAudio,
+    /// Pace emulation by audio consumption with this much latency, in milliseconds.
+    pub audio_sync_latency: Option<u64>,
+    /// Display colours, overriding the rom database.
+    pub palette: Option<Palette>,
+    pub effects: Effects,
+    /// Save the final frame of a headless run as a PNG.
+    pub screenshot_path: Option<String>,
+    /// Record every frame to a GIF, or a PNG sequence in a directory. Later
+    /// recordings in the same session get a numeric suffix.
+    pub record_path: Option<String>,
+    /// How many times screenshots and recordings enlarge the display.
+    pub capture_scale: usize,
+    pub keymap: KeymapPreset,
+    /// Key bindings applied over the preset and the rom database.
+    pub keys: Vec<(u8, String)>,
+    /// Fraction of a stick's travel that presses the key it is bound to.
+    pub axis_threshold: f32,
+    /// Seed for CXNN, random when not given.
+    pub seed: Option<u64>,
+    /// Where CXNN gets its numbers, see `random::from_spec`.
+    pub random: String,
+    /// Save every random byte CXNN uses, for replay with `--rng replay:<file>`.
+    pub record_random_path: Option<String>,
+    /// Memory for rewind history in bytes; 0 turns rewind off.
+    pub rewind_budget: usize,
+    /// Most snapshots rewind keeps.
+    pub rewind_depth: usize,
+    /// Frames between rewind snapshots.
+    pub rewind_interval: u32,
+    /// Frames to emulate ahead of the one shown, overriding the rom database.
+    pub run_ahead: Option<u32>,
+    /// Record keypad input to a movie file.
+    pub record_movie_path: Option<String>,
+    /// Replay a movie's input instead of the player's.
+    pub play_movie_path: Option<String>,
+    /// Replay a movie headless and check it ends in the recorded state.
+    pub verify_movie_path: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, Chip8Error> {
+        let mut rom_path = None;
+        let mut symbols_path = None;
+        let mut lcov_path = None;
+        let mut lint = false;
+        let mut rom_db_path = None;
+        let mut instructions_per_frame = None;
+        let mut headless_frames = None;
+        let mut vip_timing = false;
+        let mut vip_interpreter_path = None;
+        let mut beeper = BeeperSettings::default();
+        let mut wav_path = None;
+        let mut audio_sync_latency = None;
+        let mut fast_forward = vec![2.0, 4.0, 8.0];
+        let mut slow_motion = 0.5;
+        let mut off_speed_audio = OffSpeedAudio::Mute;
+        let mut palette = None;
+        let mut effects = Effects::default();
+        let mut screenshot_path = None;
+        let mut record_path = None;
+        let mut capture_scale = 4;
+        let mut keymap = KeymapPreset::Standard;
+        let mut keys = Vec::new();
+        let mut axis_threshold = keymap::DEFAULT_AXIS_THRESHOLD;
+        let mut seed = None;
+        let mut random = String::from("seeded");
+        let mut record_random_path = None;
+        let mut rewind_budget = 16 << 20;
+        let mut rewind_depth = 10 * 60 * 60;
+        let mut rewind_interval = 1;
+        let mut run_ahead = None;
+        let mut record_movie_path = None;
+        let mut play_movie_path = None;
+        let mut verify_movie_path = None;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| match args.next() {
+                Some(value) => Ok(value.clone()),
+                None => Err(Chip8Error::new(&format!("{} requires a value", name))),
+            };
+
+            match arg.as_str() {
+                "--symbols" => symbols_path = Some(value("--symbols")?),
+                "--lcov" => lcov_path = Some(value("--lcov")?),
+                "--lint" => lint = true,
+                "--rom-db" => rom_db_path = Some(value("--rom-db")?),
+                "--ipf" => instructions_per_frame = Some(number(&value("--ipf")?)?),
+                "--vip-timing" => vip_timing = true,
+                "--vip" => vip_interpreter_path = Some(value("--vip")?),
+                "--beep-frequency" => beeper.frequency = number(&value("--beep-frequency")?)?,
+                "--beep-volume" => beeper.volume = number(&value("--beep-volume")?)?,
+                "--beep-waveform" => {
+                    let name = value("--beep-waveform")?;
+                    beeper.waveform = Waveform::from_name(&name)
+                        .ok_or_else(|| Chip8Error::new(&format!("Unknown waveform {}", name)))?;
+                }
+                "--audio-sync" => audio_sync_latency = Some(number(&value("--audio-sync")?)?),
+                "--fast-forward" => {
+                    fast_forward = value("--fast-forward")?
+                        .split(',')
+                        .map(number)
+                        .collect::<Result<_, _>>()?
+                }
+                "--slow-motion" => slow_motion = number(&value("--slow-motion")?)?,
+                "--off-speed-audio" => {
+                    let name = value("--off-speed-audio")?;
+                    off_speed_audio = OffSpeedAudio::from_name(&name)
+                        .ok_or_else(|| Chip8Error::new(&format!("Unknown audio mode {}", name)))?;
+                }
+                "--wav" => wav_path = Some(value("--wav")?),
+                "--palette" => palette = Some(Palette::parse(&value("--palette")?)?),
+                "--persistence" => effects.persistence = number(&value("--persistence")?)?,
+                "--blend" => effects.blend = true,
+                "--scanlines" => effects.scanlines = number(&value("--scanlines")?)?,
+                "--grid" => effects.grid = number(&value("--grid")?)?,
+                "--scale" => effects.scale = number(&value("--scale")?)?,
+                "--screenshot" => screenshot_path = Some(value("--screenshot")?),
+                "--record" => record_path = Some(value("--record")?),
+                "--capture-scale" => capture_scale = number(&value("--capture-scale")?)?,
+                "--keymap" => {
+                    let name = value("--keymap")?;
+                    keymap = KeymapPreset::from_name(&name)
+                        .ok_or_else(|| Chip8Error::new(&format!("Unknown keymap {}", name)))?;
+                }
+                "--keys" => keys.extend(romdb::parse_overrides(&value("--keys")?)?),
+                "--stick-threshold" => axis_threshold = number(&value("--stick-threshold")?)?,
+                "--seed" => seed = Some(number(&value("--seed")?)?),
+                "--rng" => random = value("--rng")?,
+                "--record-rng" => record_random_path = Some(value("--record-rng")?),
+                "--rewind-budget" => {
+                    rewind_budget = number::<usize>(&value("--rewind-budget")?)? << 20
+                }
+                "--rewind-depth" => rewind_depth = number(&value("--rewind-depth")?)?,
+                "--rewind-interval" => rewind_interval = number(&value("--rewind-interval")?)?,
+                "--run-ahead" => run_ahead = Some(number(&value("--run-ahead")?)?),
+                "--record-movie" => record_movie_path = Some(value("--record-movie")?),
+                "--play-movie" => play_movie_path = Some(value("--play-movie")?),
+                "--verify-movie" => verify_movie_path = Some(value("--verify-movie")?),
+                "--headless" => headless_frames = Some(number(&value("--headless")?)?),
+                _ if arg.starts_with("--") => {
+                    return Err(Chip8Error::new(&format!("Unknown option {}", arg)))
+                }
+                _ => rom_path = Some(arg.clone()),
+            }
+        }
+
+        if fast_forward.is_empty() || fast_forward.iter().chain([&slow_motion]).any(|&s| s <= 0.0) {
+            return Err(Chip8Error::new("Speeds must be greater than zero"));
+        }
+        // Movie hashes cover the CHIP-8 machine, not an emulated VIP board
+        if vip_interpreter_path.is_some()
+            && (record_movie_path.is_some()
+                || play_movie_path.is_some()
+                || verify_movie_path.is_some())
+        {
+            return Err(Chip8Error::new("Movies can't be used with --vip"));
+        }
+        // A movie stores only the seed, so other sources wouldn't replay
+        if random != "seeded"
+            && (record_movie_path.is_some()
+                || play_movie_path.is_some()
+                || verify_movie_path.is_some())
+        {
+            return Err(Chip8Error::new("Movies need the seeded random source"));
+        }
+
+        match rom_path {
+            Some(rom_path) => Ok(Options {
+                rom_path,
+                symbols_path,
+                lcov_path,
+                lint,
+                rom_db_path,
+                instructions_per_frame,
+                headless_frames,
+                vip_timing,
+                vip_interpreter_path,
+                beeper,
+                wav_path,
+                audio_sync_latency,
+                fast_forward,
+                slow_motion,
+                off_speed_audio,
+                palette,
+                effects,
+                screenshot_path,
+                record_path,
+                capture_scale,
+                keymap,
+                keys,
+                axis_threshold,
+                seed,
+                random,
+                record_random_path,
+                rewind_budget,
+                rewind_depth,
+                rewind_interval,
+                run_ahead,
+                record_movie_path,
+                play_movie_path,
+                verify_movie_path,
+            }),
+            None => Err(Chip8Error::new("Rom path is required")),
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, Chip8Error> {
+    value
+        .parse()
+        .map_err(|_| Chip8Error::new(&format!("Invalid number {}", value)))
+}
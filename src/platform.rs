@@ -0,0 +1,55 @@
+use crate::quirks::QuirksPreset;
+
+/// The CHIP-8 dialect a ROM is written for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` accepts.
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    /// Number of display bitplanes.
+    pub fn planes(self) -> usize {
+        match self {
+            Platform::XoChip => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn default_quirks(self) -> QuirksPreset {
+        match self {
+            Platform::Chip8 => QuirksPreset::Chip8,
+            Platform::SuperChip => QuirksPreset::SuperChip,
+            Platform::XoChip => QuirksPreset::XoChip,
+        }
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::SuperChip => write!(f, "SUPER-CHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
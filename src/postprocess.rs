@@ -0,0 +1,226 @@
+use crate::error::Chip8Error;
+use crate::graphics::Graphics;
+
+/// Colour for each combination of plane bits, background first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_name("red").unwrap()
+    }
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Result<Palette, Chip8Error> {
+        if colors.is_empty() {
+            return Err(Chip8Error::new("A palette needs at least one colour"));
+        }
+        Ok(Palette { colors })
+    }
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "red" => vec![[0, 0, 0], [128, 0, 0], [0, 128, 0], [128, 128, 0]],
+            "white" => vec![[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]],
+            "green" => vec![[0, 16, 0], [51, 255, 51], [26, 160, 26], [13, 80, 13]],
+            "amber" => vec![[16, 8, 0], [255, 176, 0], [192, 112, 0], [96, 56, 0]],
+            "octo" => vec![
+                [0x99, 0x66, 0x00],
+                [0xFF, 0xCC, 0x00],
+                [0xFF, 0x66, 0x00],
+                [0x66, 0x22, 0x00],
+            ],
+            _ => return None,
+        };
+        Some(Palette { colors })
+    }
+
+    /// A preset name, or comma separated RRGGBB colours.
+    pub fn parse(value: &str) -> Result<Palette, Chip8Error> {
+        if let Some(palette) = Palette::from_name(value) {
+            return Ok(palette);
+        }
+
+        let colors = value
+            .split(',')
+            .map(|color| {
+                u32::from_str_radix(color.trim().trim_start_matches('#'), 16)
+                    .map(|rgb| [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+                    .map_err(|_| Chip8Error::new(&format!("Invalid colour {}", color)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Palette::new(colors)
+    }
+
+    pub fn color(&self, planes: u8) -> [u8; 3] {
+        self.colors[planes as usize % self.colors.len()]
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Effects {
+    /// Fraction of its distance from the off colour a pixel keeps each frame
+    /// after turning off.
+    pub persistence: f32,
+    /// Average each frame with the previous one, hiding XOR flicker.
+    pub blend: bool,
+    /// Darkening of the last line of every pixel, from 0.0 to 1.0.
+    pub scanlines: f32,
+    /// Darkening of the right and bottom edge of every pixel, from 0.0 to 1.0.
+    pub grid: f32,
+    /// Output pixels per display pixel. Scanlines and the grid need at least 2.
+    pub scale: usize,
+}
+
+impl Effects {
+    pub fn is_none(&self) -> bool {
+        self.persistence == 0.0
+            && !self.blend
+            && self.scanlines == 0.0
+            && self.grid == 0.0
+            && self.scale <= 1
+    }
+}
+
+/// Turns the bitplanes into the picture players see. It keeps its own state
+/// between frames and is advanced once per emulated frame, so the output only
+/// depends on the sequence of frames and is the same with or without a window.
+#[derive(Clone)]
+pub struct PostProcessor {
+    palette: Palette,
+    effects: Effects,
+    width: usize,
+    height: usize,
+    previous: Vec<[f32; 3]>,
+    phosphor: Vec<[f32; 3]>,
+}
+
+impl PostProcessor {
+    pub fn new(palette: Palette, effects: Effects) -> PostProcessor {
+        PostProcessor {
+            palette,
+            effects,
+            width: 0,
+            height: 0,
+            previous: vec![],
+            phosphor: vec![],
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    fn scale(&self) -> usize {
+        self.effects.scale.max(1)
+    }
+
+    pub fn output_width(&self) -> usize {
+        self.width * self.scale()
+    }
+
+    pub fn output_height(&self) -> usize {
+        self.height * self.scale()
+    }
+
+    /// Folds the current picture into the processor's state.
+    pub fn advance(&mut self, graphics: &Graphics) {
+        // Afterglow fades toward the background rather than black, so light
+        // palettes don't leave dark trails
+        let off = self.palette.color(0).map(|c| c as f32);
+        if graphics.width() != self.width || graphics.height() != self.height {
+            self.width = graphics.width();
+            self.height = graphics.height();
+            self.previous = vec![off; self.width * self.height];
+            self.phosphor = vec![off; self.width * self.height];
+        }
+
+        let distance =
+            |color: &[f32; 3]| -> f32 { (0..3).map(|c| (color[c] - off[c]).abs()).sum() };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let base = self.palette.color(graphics.pixel(x, y)).map(|c| c as f32);
+
+                let previous = self.previous[i];
+                let color: [f32; 3] = std::array::from_fn(|c| match self.effects.blend {
+                    true => (base[c] + previous[c]) / 2.0,
+                    false => base[c],
+                });
+                let phosphor = self.phosphor[i];
+                let decayed: [f32; 3] = std::array::from_fn(|c| {
+                    off[c] + (phosphor[c] - off[c]) * self.effects.persistence
+                });
+                self.phosphor[i] = if distance(&decayed) > distance(&color) {
+                    decayed
+                } else {
+                    color
+                };
+                self.previous[i] = base;
+            }
+        }
+    }
+
+    /// Renders the processed picture at `output_width` by `output_height`
+    /// into `data`, resizing it to fit.
+    pub fn render(&self, data: &mut Vec<u8>) {
+        let scale = self.scale();
+        let width = self.output_width();
+        data.resize(width * self.output_height() * 4, 0);
+
+        for (oy, row) in data.chunks_exact_mut(width * 4).enumerate() {
+            let (y, sub_y) = (oy / scale, oy % scale);
+            for (ox, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let (x, sub_x) = (ox / scale, ox % scale);
+
+                let mut brightness = 1.0;
+                if scale > 1 && sub_y == scale - 1 {
+                    brightness *= 1.0 - self.effects.scanlines;
+                }
+                if scale > 1 && (sub_x == scale - 1 || sub_y == scale - 1) {
+                    brightness *= 1.0 - self.effects.grid;
+                }
+
+                let color = self.phosphor[y * self.width + x];
+                for c in 0..3 {
+                    pixel[c] = (color[c] * brightness).round().clamp(0.0, 255.0) as u8;
+                }
+                pixel[3] = 255;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phosphor_decays_toward_off_colour() {
+        let palette = Palette::new(vec![[200, 200, 200], [0, 0, 0]]).unwrap();
+        let effects = Effects {
+            persistence: 0.5,
+            scale: 1,
+            ..Effects::default()
+        };
+        let mut processor = PostProcessor::new(palette, effects);
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(0, 0, true);
+        processor.advance(&graphics);
+        graphics.set_pixel(0, 0, false);
+        processor.advance(&graphics);
+
+        let mut data = vec![];
+        processor.render(&mut data);
+        assert_eq!(&data[..3], &[100, 100, 100]);
+        assert_eq!(&data[4..7], &[200, 200, 200]);
+    }
+}
@@ -0,0 +1,129 @@
+/// Behaviours that differ between CHIP-8 interpreters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// FX55 and FX65 leave I pointing past the last register they touched.
+    pub memory_increments_index: bool,
+    /// 8XY6 and 8XYE shift VX in place instead of shifting VY into VX.
+    pub shift_in_place: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// DXYN waits for the vertical blank before drawing.
+    pub display_wait: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping.
+    pub clip_sprites: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum QuirksPreset {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl QuirksPreset {
+    pub fn from_name(name: &str) -> Option<QuirksPreset> {
+        match name {
+            "chip8" => Some(QuirksPreset::Chip8),
+            "schip" => Some(QuirksPreset::SuperChip),
+            "xochip" => Some(QuirksPreset::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            QuirksPreset::Chip8 => Quirks {
+                vf_reset: true,
+                memory_increments_index: true,
+                shift_in_place: false,
+                jump_uses_vx: false,
+                display_wait: true,
+                clip_sprites: true,
+            },
+            QuirksPreset::SuperChip => Quirks {
+                vf_reset: false,
+                memory_increments_index: false,
+                shift_in_place: true,
+                jump_uses_vx: true,
+                display_wait: false,
+                clip_sprites: true,
+            },
+            QuirksPreset::XoChip => Quirks {
+                vf_reset: false,
+                memory_increments_index: true,
+                shift_in_place: false,
+                jump_uses_vx: false,
+                display_wait: false,
+                clip_sprites: false,
+            },
+        }
+    }
+}
+
+impl Quirks {
+    fn flags(&mut self) -> [(&'static str, &mut bool); 6] {
+        [
+            ("vf_reset", &mut self.vf_reset),
+            ("memory_increments_index", &mut self.memory_increments_index),
+            ("shift_in_place", &mut self.shift_in_place),
+            ("jump_uses_vx", &mut self.jump_uses_vx),
+            ("display_wait", &mut self.display_wait),
+            ("clip_sprites", &mut self.clip_sprites),
+        ]
+    }
+
+    /// Parses the space separated names of the enabled quirks, as written by
+    /// `Display`.
+    pub fn parse(value: &str) -> Option<Quirks> {
+        let mut quirks = Quirks {
+            vf_reset: false,
+            memory_increments_index: false,
+            shift_in_place: false,
+            jump_uses_vx: false,
+            display_wait: false,
+            clip_sprites: false,
+        };
+        for name in value.split_whitespace().filter(|&name| name != "none") {
+            let (_, flag) = quirks
+                .flags()
+                .into_iter()
+                .find(|(flag_name, _)| *flag_name == name)?;
+            *flag = true;
+        }
+        Some(quirks)
+    }
+}
+
+impl std::fmt::Display for Quirks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut quirks = *self;
+        let enabled: Vec<_> = quirks
+            .flags()
+            .into_iter()
+            .filter(|(_, flag)| **flag)
+            .map(|(name, _)| name)
+            .collect();
+        match enabled.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", enabled.join(" ")),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        QuirksPreset::Chip8.quirks()
+    }
+}
+
+impl std::fmt::Display for QuirksPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuirksPreset::Chip8 => write!(f, "CHIP-8"),
+            QuirksPreset::SuperChip => write!(f, "SUPER-CHIP"),
+            QuirksPreset::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use crate::error::Chip8Error;
+
+/// Where CXNN gets its random bytes. The machine owns one, so runs can be
+/// made reproducible by choosing a deterministic source.
+pub trait RandomSource: Send {
+    fn next_byte(&mut self) -> u8;
+
+    /// An independent copy that continues from the same position.
+    fn duplicate(&self) -> Box<dyn RandomSource>;
+
+    /// The generator's position, for snapshots and state hashes.
+    fn save_state(&self) -> Vec<u8>;
+
+    fn load_state(&mut self, state: &[u8]);
+
+    /// Takes the problem the source last ran into, such as running out of
+    /// recorded bytes. Sources that can't fail never have one.
+    fn take_error(&mut self) -> Option<Chip8Error> {
+        None
+    }
+}
+
+/// Reads a `usize` position saved as eight little endian bytes.
+fn position(state: &[u8]) -> usize {
+    state.get(..8).map_or(0, |bytes| {
+        u64::from_le_bytes(bytes.try_into().unwrap()) as usize
+    })
+}
+
+/// A seeded xorshift generator. It is implemented here rather than taken
+/// from `rand` so a seed produces the same bytes in every build, which movies
+/// depend on.
+#[derive(Clone)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        // Run the seed through splitmix64 so small seeds still start from a
+        // well mixed, non-zero state.
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        SeededRandom {
+            state: state.max(1),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn duplicate(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.state = (position(state) as u64).max(1);
+    }
+}
+
+/// Repeats the given bytes forever, for tests that need known values.
+#[derive(Clone)]
+pub struct FixedRandom {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl FixedRandom {
+    pub fn new(bytes: Vec<u8>) -> Result<FixedRandom, Chip8Error> {
+        if bytes.is_empty() {
+            return Err(Chip8Error::new(
+                "A fixed random sequence needs at least one byte",
+            ));
+        }
+        Ok(FixedRandom { bytes, position: 0 })
+    }
+
+    /// Parses space or comma separated hex bytes.
+    pub fn parse(value: &str) -> Result<FixedRandom, Chip8Error> {
+        let bytes = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|byte| !byte.is_empty())
+            .map(|byte| {
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| Chip8Error::new(&format!("Invalid random byte {}", byte)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        FixedRandom::new(bytes)
+    }
+}
+
+impl RandomSource for FixedRandom {
+    fn duplicate(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes[self.position];
+        self.position = (self.position + 1) % self.bytes.len();
+        byte
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        (self.position as u64).to_le_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.position = position(state) % self.bytes.len();
+    }
+}
+
+/// Plays back a stream written by `RecordingRandom`.
+#[derive(Clone)]
+pub struct ReplayRandom {
+    bytes: Vec<u8>,
+    position: usize,
+    error: Option<Chip8Error>,
+}
+
+impl ReplayRandom {
+    pub fn load(path: &str) -> Result<ReplayRandom, Chip8Error> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| Chip8Error::new(&format!("Failed to read {}: {}", path, e)))?;
+        Ok(ReplayRandom {
+            bytes,
+            position: 0,
+            error: None,
+        })
+    }
+
+    /// Whether the run has asked for more bytes than were recorded.
+    pub fn is_exhausted(&self) -> bool {
+        self.position > self.bytes.len()
+    }
+}
+
+impl RandomSource for ReplayRandom {
+    fn duplicate(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes.get(self.position).copied().unwrap_or(0);
+        if self.position == self.bytes.len() {
+            self.error = Some(Chip8Error::new(
+                "Random stream exhausted, continuing with zeros",
+            ));
+        }
+        self.position += 1;
+        byte
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        (self.position as u64).to_le_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.position = position(state);
+    }
+
+    fn take_error(&mut self) -> Option<Chip8Error> {
+        self.error.take()
+    }
+}
+
+/// Passes another source through, writing every byte it produces to a file
+/// that `ReplayRandom` can play back.
+pub struct RecordingRandom {
+    source: Box<dyn RandomSource>,
+    file: BufWriter<File>,
+    written: u64,
+    error: Option<Chip8Error>,
+}
+
+impl RecordingRandom {
+    pub fn create(
+        path: &str,
+        source: Box<dyn RandomSource>,
+    ) -> Result<RecordingRandom, Chip8Error> {
+        let file = File::create(path)
+            .map_err(|e| Chip8Error::new(&format!("Failed to create {}: {}", path, e)))?;
+        Ok(RecordingRandom {
+            source,
+            file: BufWriter::new(file),
+            written: 0,
+            error: None,
+        })
+    }
+}
+
+impl RandomSource for RecordingRandom {
+    /// Copies follow the recorded source but don't write to the recording.
+    fn duplicate(&self) -> Box<dyn RandomSource> {
+        self.source.duplicate()
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.source.next_byte();
+        if let Err(e) = self.file.write_all(&[byte]) {
+            self.error = Some(Chip8Error::new(&format!(
+                "Failed to record random byte: {}",
+                e
+            )));
+        }
+        self.written += 1;
+        byte
+    }
+
+    /// Saves the source's state followed by how much had been recorded.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.source.save_state();
+        state.extend_from_slice(&self.written.to_le_bytes());
+        state
+    }
+
+    /// Also cuts the recording back, so bytes from an abandoned future are
+    /// not replayed.
+    fn load_state(&mut self, state: &[u8]) {
+        let split = state.len().saturating_sub(8);
+        self.source.load_state(&state[..split]);
+        let written = position(&state[split..]) as u64;
+        // Run-ahead restores every frame, usually to where it already is
+        if written == self.written {
+            return;
+        }
+        self.written = written;
+
+        let result = self.file.flush().and_then(|_| {
+            let file = self.file.get_mut();
+            file.set_len(self.written)?;
+            file.seek(SeekFrom::Start(self.written)).map(|_| ())
+        });
+        if let Err(e) = result {
+            self.error = Some(Chip8Error::new(&format!(
+                "Failed to rewind random recording: {}",
+                e
+            )));
+        }
+    }
+
+    fn take_error(&mut self) -> Option<Chip8Error> {
+        self.error.take().or_else(|| self.source.take_error())
+    }
+}
+
+/// The COSMAC VIP interpreter's generator. It steps a counter through the
+/// interpreter's second page, adds the byte found there to an accumulator
+/// and returns the accumulator, so its output depends on the interpreter
+/// image it is given.
+#[derive(Clone)]
+pub struct VipRandom {
+    table: [u8; 256],
+    counter: u8,
+    accumulator: u8,
+}
+
+impl VipRandom {
+    /// `interpreter` is the image loaded at address 0, at least 512 bytes.
+    pub fn new(interpreter: &[u8]) -> Result<VipRandom, Chip8Error> {
+        let page = interpreter
+            .get(0x100..0x200)
+            .ok_or_else(|| Chip8Error::new("The VIP interpreter image is too short"))?;
+        let mut table = [0; 256];
+        table.copy_from_slice(page);
+        Ok(VipRandom {
+            table,
+            counter: 0,
+            accumulator: 0,
+        })
+    }
+}
+
+impl RandomSource for VipRandom {
+    fn duplicate(&self) -> Box<dyn RandomSource> {
+        Box::new(self.clone())
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.counter = self.counter.wrapping_add(1);
+        self.accumulator = self
+            .accumulator
+            .wrapping_add(self.table[self.counter as usize]);
+        self.accumulator
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        vec![self.counter, self.accumulator]
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if let [counter, accumulator] = *state {
+            self.counter = counter;
+            self.accumulator = accumulator;
+        }
+    }
+}
+
+/// Builds a source from its command line form: `seeded`, `fixed:<hex bytes>`,
+/// `replay:<file>` or `vip:<interpreter image>`.
+pub fn from_spec(spec: &str, seed: u64) -> Result<Box<dyn RandomSource>, Chip8Error> {
+    let (kind, argument) = spec.split_once(':').unwrap_or((spec, ""));
+    match kind {
+        "seeded" => Ok(Box::new(SeededRandom::new(seed))),
+        "fixed" => Ok(Box::new(FixedRandom::parse(argument)?)),
+        "replay" => Ok(Box::new(ReplayRandom::load(argument)?)),
+        "vip" => {
+            let interpreter = std::fs::read(argument)
+                .map_err(|e| Chip8Error::new(&format!("Failed to read {}: {}", argument, e)))?;
+            Ok(Box::new(VipRandom::new(&interpreter)?))
+        }
+        _ => Err(Chip8Error::new(&format!("Unknown random source {}", spec))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Machine;
+
+    fn take(source: &mut dyn RandomSource, count: usize) -> Vec<u8> {
+        (0..count).map(|_| source.next_byte()).collect()
+    }
+
+    fn scratch_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("chip8-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn seeded_sequence_is_stable() {
+        // Movies depend on these never changing
+        let mut random = SeededRandom::new(1);
+        assert_eq!(
+            take(&mut random, 8),
+            vec![71, 120, 109, 241, 59, 215, 144, 20]
+        );
+        let mut other = SeededRandom::new(2);
+        assert_ne!(take(&mut other, 8), take(&mut SeededRandom::new(1), 8));
+    }
+
+    #[test]
+    fn fixed_wraps_around() {
+        let mut random = FixedRandom::parse("01, 02 ff").unwrap();
+        assert_eq!(take(&mut random, 7), vec![1, 2, 0xFF, 1, 2, 0xFF, 1]);
+        assert!(FixedRandom::parse("").is_err());
+        assert!(FixedRandom::parse("100").is_err());
+    }
+
+    #[test]
+    fn replay_plays_back_recording() {
+        let path = scratch_path("replay.bin");
+        let mut recording = RecordingRandom::create(&path, Box::new(SeededRandom::new(7))).unwrap();
+        let recorded = take(&mut recording, 16);
+        drop(recording);
+
+        let mut replay = ReplayRandom::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(take(&mut replay, 16), recorded);
+        assert!(!replay.is_exhausted());
+        assert!(replay.take_error().is_none());
+        assert_eq!(replay.next_byte(), 0);
+        assert!(replay.is_exhausted());
+        assert!(replay.take_error().is_some());
+    }
+
+    #[test]
+    fn recording_rewinds_with_snapshots() {
+        let path = scratch_path("rewound.bin");
+        let mut recording =
+            RecordingRandom::create(&path, Box::new(FixedRandom::parse("1 2 3 4").unwrap()))
+                .unwrap();
+        take(&mut recording, 2);
+        let state = recording.save_state();
+        take(&mut recording, 2);
+        recording.load_state(&state);
+        assert_eq!(take(&mut recording, 1), vec![3]);
+        drop(recording);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn vip_walks_interpreter_table() {
+        let mut interpreter = vec![0; 0x200];
+        interpreter[0x101] = 5;
+        interpreter[0x102] = 10;
+        interpreter[0x103] = 0xFF;
+        interpreter[0x100] = 1;
+        let mut random = VipRandom::new(&interpreter).unwrap();
+        // Accumulates table[1], table[2], table[3], ...
+        assert_eq!(take(&mut random, 4), vec![5, 15, 14, 14]);
+        for _ in 4..255 {
+            random.next_byte();
+        }
+        // The counter wraps to 0, adding table[0]
+        assert_eq!(random.next_byte(), 15);
+        assert!(VipRandom::new(&[0; 0x1FF]).is_err());
+    }
+
+    #[test]
+    fn sources_resume_from_saved_state() {
+        let sources: Vec<Box<dyn RandomSource>> = vec![
+            Box::new(SeededRandom::new(3)),
+            Box::new(FixedRandom::parse("9 8 7").unwrap()),
+            Box::new(VipRandom::new(&(0..=255).cycle().take(0x200).collect::<Vec<u8>>()).unwrap()),
+        ];
+        for mut source in sources {
+            take(source.as_mut(), 5);
+            let state = source.save_state();
+            let expected = take(source.as_mut(), 5);
+            source.load_state(&state);
+            assert_eq!(take(source.as_mut(), 5), expected);
+        }
+    }
+
+    #[test]
+    fn from_spec_builds_each_source() {
+        assert_eq!(
+            take(from_spec("seeded", 1).unwrap().as_mut(), 8),
+            take(&mut SeededRandom::new(1), 8)
+        );
+        assert_eq!(
+            take(from_spec("fixed:aa 55", 0).unwrap().as_mut(), 3),
+            vec![0xAA, 0x55, 0xAA]
+        );
+        assert!(from_spec("replay:/nonexistent/chip8.bin", 0).is_err());
+        assert!(from_spec("dice", 0).is_err());
+    }
+
+    #[test]
+    fn machine_snapshot_restores_random_position() {
+        // V0 = random & FF, jump back
+        let mut machine = Machine::builder()
+            .rom(&[0xC0, 0xFF, 0x12, 0x00])
+            .random(Box::new(FixedRandom::parse("10 20 30").unwrap()))
+            .build()
+            .unwrap();
+        machine.step();
+        let snapshot = machine.snapshot();
+        machine.step();
+        machine.step();
+        assert_eq!(machine.cpu().reg(crate::cpu::Register::V0), 0x20);
+        machine.restore(&snapshot).unwrap();
+        machine.step();
+        machine.step();
+        assert_eq!(machine.cpu().reg(crate::cpu::Register::V0), 0x20);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use crate::error::Chip8Error;
+use crate::platform::Platform;
+use crate::quirks::QuirksPreset;
+
+const BUNDLED: &str = include_str!("../roms.db");
+
+/// Settings known for a specific ROM. Anything left unset falls back to
+/// detection or the emulator defaults.
+#[derive(Clone, Default, Debug)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<QuirksPreset>,
+    pub tick_rate: Option<u32>,
+    /// Frames to run ahead of the shown one to hide input lag.
+    pub run_ahead: Option<u32>,
+    /// CHIP-8 key and the SDL key name that presses it.
+    pub keys: Vec<(u8, String)>,
+    /// Background first, then one colour per plane combination.
+    pub colors: Vec<[u8; 3]>,
+}
+
+pub struct RomDatabase {
+    entries: HashMap<String, RomInfo>,
+}
+
+pub fn sha1_hex(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+impl RomDatabase {
+    pub fn parse(text: &str) -> Result<RomDatabase, Chip8Error> {
+        let mut entries = HashMap::new();
+        let mut current: Option<(String, RomInfo)> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid =
+                |what: &str| Chip8Error::new(&format!("Invalid {} on line {}", what, number + 1));
+
+            if let Some(digest) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if digest.len() != 40 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(invalid("digest"));
+                }
+                if let Some((digest, info)) = current.take() {
+                    entries.insert(digest, info);
+                }
+                current = Some((digest.to_lowercase(), RomInfo::default()));
+                continue;
+            }
+
+            let Some((_, info)) = current.as_mut() else {
+                return Err(invalid("entry without a digest"));
+            };
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid("line"))?;
+            let value = value.trim();
+
+            match key.trim() {
+                "title" => info.title = Some(String::from(value)),
+                "author" => info.author = Some(String::from(value)),
+                "platform" => {
+                    info.platform =
+                        Some(Platform::from_name(value).ok_or_else(|| invalid("platform"))?)
+                }
+                "quirks" => {
+                    info.quirks =
+                        Some(QuirksPreset::from_name(value).ok_or_else(|| invalid("quirks"))?)
+                }
+                "tick_rate" => {
+                    info.tick_rate = Some(value.parse().map_err(|_| invalid("tick rate"))?)
+                }
+                "run_ahead" => {
+                    info.run_ahead = Some(value.parse().map_err(|_| invalid("run ahead"))?)
+                }
+                "keys" => info
+                    .keys
+                    .extend(parse_overrides(value).map_err(|_| invalid("key"))?),
+                "colors" => {
+                    for color in value.split_whitespace() {
+                        let rgb = u32::from_str_radix(color.trim_start_matches('#'), 16)
+                            .map_err(|_| invalid("color"))?;
+                        info.colors
+                            .push([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]);
+                    }
+                }
+                _ => return Err(invalid("key")),
+            }
+        }
+
+        if let Some((digest, info)) = current {
+            entries.insert(digest, info);
+        }
+
+        Ok(RomDatabase { entries })
+    }
+
+    /// The database compiled into the emulator.
+    pub fn bundled() -> RomDatabase {
+        RomDatabase::parse(BUNDLED).expect("bundled rom database is valid")
+    }
+
+    pub fn load(path: &str) -> Result<RomDatabase, Chip8Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => RomDatabase::parse(&text),
+            Err(e) => Err(Chip8Error::new(&format!(
+                "Failed to read rom database {}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    /// Adds the entries of `other`, replacing any with the same digest.
+    pub fn extend(&mut self, other: RomDatabase) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.entries.get(&sha1_hex(rom))
+    }
+}
+
+/// Parses space separated `<hex key>:<key name>` pairs, as used by the `keys`
+/// field and the `--keys` option.
+pub fn parse_overrides(value: &str) -> Result<Vec<(u8, String)>, Chip8Error> {
+    value
+        .split_whitespace()
+        .map(|binding| {
+            let invalid = || Chip8Error::new(&format!("Invalid key binding {}", binding));
+            let (key, name) = binding.split_once(':').ok_or_else(invalid)?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(invalid)?;
+            Ok((key, String::from(name)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IBM_LOGO: &str = "00e0a22a600c6108d01f7009a239d01fa2487008d01f7004a257d01f7008a266\
+        d01f7008a275d01f1228ff00ff003c003c003c003c00ff00ffff00ff0038003f003f003800ff00ff8000\
+        e000e00080008000e000e00080f800fc003e003f003b003900f800f803000700\
+        0f00bf00fb00f300e30043e000e0008000800080008000e000e0";
+
+    const MAZE: &str = "a21ec2013201a21ad0147004304012006000710431201200121880402010204080\
+        10";
+
+    const DIGEST: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn bundled_entries_match_their_roms() {
+        let database = RomDatabase::bundled();
+        let roms = [("IBM Logo", IBM_LOGO), ("Maze", MAZE)];
+        assert_eq!(database.entries.len(), roms.len());
+        for (title, rom) in roms {
+            let info = database.lookup(&hex(rom)).unwrap();
+            assert_eq!(info.title.as_deref(), Some(title));
+            assert_eq!(info.platform, Some(Platform::Chip8));
+        }
+        let info = database.lookup(&hex(IBM_LOGO)).unwrap();
+        assert_eq!(info.colors, vec![[0, 0, 0], [0xFF, 0xFF, 0xFF]]);
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let database = RomDatabase::parse(&format!(
+            "# comment\n\
+             [{}]\n\
+             title = Game\n\
+             author = Someone\n\
+             platform = schip\n\
+             quirks = xochip\n\
+             tick_rate = 30\n\
+             keys = 5:W 8:S\n\
+             colors = #102030 ffffff\n",
+            DIGEST.to_uppercase()
+        ))
+        .unwrap();
+        let info = &database.entries[DIGEST];
+        assert_eq!(info.author.as_deref(), Some("Someone"));
+        assert_eq!(info.platform, Some(Platform::SuperChip));
+        assert_eq!(info.quirks, Some(QuirksPreset::XoChip));
+        assert_eq!(info.tick_rate, Some(30));
+        assert_eq!(
+            info.keys,
+            vec![(5, String::from("W")), (8, String::from("S"))]
+        );
+        assert_eq!(info.colors[0], [0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn rejects_malformed_digests() {
+        assert!(RomDatabase::parse("[abcdef]").is_err());
+        assert!(RomDatabase::parse(&format!("[{}0]", DIGEST)).is_err());
+        assert!(RomDatabase::parse(&format!("[{}]", DIGEST.replace('a', "g"))).is_err());
+    }
+
+    #[test]
+    fn rejects_fields_outside_an_entry() {
+        assert!(RomDatabase::parse("title = Game").is_err());
+    }
+}
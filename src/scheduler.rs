@@ -0,0 +1,329 @@
+use std::time::Instant;
+
+use crate::coverage::Coverage;
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::graphics::Graphics;
+use crate::input::Input;
+use crate::instruction::Instruction;
+use crate::mmu::{Mmu, ADDRESS_MASK};
+use crate::snapshot::StateReader;
+use crate::timing::{VipTiming, SKIP_CYCLES};
+
+/// Emulated frames per second; the timers tick once per frame.
+pub const FRAME_RATE: u32 = 60;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+
+/// Most frames `Pacer` will ask for at once before it gives up on catching up.
+const MAX_CATCH_UP_FRAMES: u64 = 4;
+
+/// Runs the machine in 60 Hz frames of emulated time.
+#[derive(Clone)]
+pub struct Scheduler {
+    instructions_per_frame: u32,
+    frame: u64,
+    /// Replaces the fixed instruction count with VIP cycle budgets.
+    vip_timing: Option<VipTiming>,
+}
+
+impl Scheduler {
+    pub fn new(instructions_per_frame: u32) -> Scheduler {
+        Scheduler {
+            instructions_per_frame,
+            frame: 0,
+            vip_timing: None,
+        }
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn set_vip_timing(&mut self, enabled: bool) {
+        self.vip_timing = if enabled {
+            Some(VipTiming::new())
+        } else {
+            None
+        };
+    }
+
+    pub fn vip_timing(&self) -> bool {
+        self.vip_timing.is_some()
+    }
+
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.frame.to_le_bytes());
+        let carry = self.vip_timing.as_ref().map_or(0, |timing| timing.carry());
+        out.extend_from_slice(&carry.to_le_bytes());
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.frame = reader.u64()?;
+        let carry = reader.u32()?;
+        if let Some(timing) = &mut self.vip_timing {
+            timing.set_carry(carry);
+        }
+        Ok(())
+    }
+
+    /// Number of frames run so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Runs one frame of instructions, then ticks the timers. With the
+    /// display wait quirk a draw ends the frame early, as it waits for vblank.
+    /// A halted cpu runs nothing.
+    pub fn run_frame(
+        &mut self,
+        cpu: &mut Cpu,
+        mmu: &mut Mmu,
+        graphics: &mut Graphics,
+        input: &Input,
+        coverage: &mut Coverage,
+    ) {
+        if let Some(timing) = &mut self.vip_timing {
+            Scheduler::run_vip_frame(timing, cpu, mmu, graphics, input, coverage);
+            cpu.tick_timers();
+            self.frame += 1;
+            return;
+        }
+
+        for _ in 0..self.instructions_per_frame {
+            if cpu.is_halted() {
+                break;
+            }
+            let pc = cpu.pc();
+            let instruction = cpu.step(mmu, graphics, input);
+            coverage.record(pc, &instruction, cpu.pc());
+
+            if cpu.quirks().display_wait && matches!(instruction, Instruction::Draw(..)) {
+                break;
+            }
+        }
+
+        cpu.tick_timers();
+        self.frame += 1;
+    }
+
+    /// Runs instructions until the frame's cycle budget is spent. The VIP
+    /// draws right after vblank, so a draw ends the frame and its cost is
+    /// charged to the next one.
+    fn run_vip_frame(
+        timing: &mut VipTiming,
+        cpu: &mut Cpu,
+        mmu: &mut Mmu,
+        graphics: &mut Graphics,
+        input: &Input,
+        coverage: &mut Coverage,
+    ) {
+        let mut budget = timing.start_frame();
+
+        while budget > 0 && !cpu.is_halted() {
+            let pc = cpu.pc();
+            let mut cost = VipTiming::cycles(&Instruction::decode(mmu.peek16(pc)), cpu);
+            let instruction = cpu.step(mmu, graphics, input);
+            coverage.record(pc, &instruction, cpu.pc());
+
+            if instruction.is_skip() && cpu.pc() != (pc + 2) & ADDRESS_MASK {
+                cost += SKIP_CYCLES;
+            }
+
+            if matches!(instruction, Instruction::Draw(..)) {
+                timing.defer(cost);
+                break;
+            }
+            if cost > budget {
+                timing.defer(cost - budget);
+            }
+            budget = budget.saturating_sub(cost);
+        }
+    }
+}
+
+/// Maps wall-clock time onto emulated frames so a frontend runs at real speed
+/// regardless of how often it polls.
+pub struct Pacer {
+    start: Instant,
+    frames: u64,
+    speed: f32,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pacer {
+    pub fn new() -> Pacer {
+        Pacer {
+            start: Instant::now(),
+            frames: 0,
+            speed: 1.0,
+        }
+    }
+
+    /// Starts counting from now, forgetting any time spent paused.
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.frames = 0;
+    }
+
+    /// Runs frames `speed` times as fast as real time.
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed != self.speed {
+            self.speed = speed;
+            self.reset();
+        }
+    }
+
+    /// Emulated frames that have become due since the last call. When the host
+    /// falls far behind the backlog is dropped instead of run all at once.
+    pub fn frames_due(&mut self) -> u32 {
+        let elapsed = self.start.elapsed();
+        let target = (elapsed.as_secs_f64() * FRAME_RATE as f64 * self.speed as f64) as u64;
+        let due = target.saturating_sub(self.frames);
+
+        let limit = MAX_CATCH_UP_FRAMES * (self.speed.ceil() as u64).max(1);
+        if due > limit {
+            self.frames = target - limit;
+        }
+        let due = due.min(limit);
+        self.frames += due;
+        due as u32
+    }
+}
+
+/// Pause, frame advance, fast-forward and slow motion, as driven by a
+/// frontend's hotkeys.
+pub struct SpeedControl {
+    paused: bool,
+    /// A single frame requested while paused.
+    advance: bool,
+    fast_forward: bool,
+    multipliers: Vec<f32>,
+    multiplier: usize,
+    slow_motion: bool,
+    slow_factor: f32,
+}
+
+impl SpeedControl {
+    pub fn new(multipliers: Vec<f32>, slow_factor: f32) -> SpeedControl {
+        SpeedControl {
+            paused: false,
+            advance: false,
+            fast_forward: false,
+            multipliers,
+            multiplier: 0,
+            slow_motion: false,
+            slow_factor,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = false;
+    }
+
+    /// Runs one frame then stays paused, pausing first if running.
+    pub fn advance(&mut self) {
+        self.paused = true;
+        self.advance = true;
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    /// Moves on to the next fast-forward multiplier.
+    pub fn cycle_multiplier(&mut self) {
+        self.multiplier = (self.multiplier + 1) % self.multipliers.len().max(1);
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+    }
+
+    fn fast_forward_multiplier(&self) -> f32 {
+        self.multipliers
+            .get(self.multiplier)
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// How many times real time emulation should run at.
+    pub fn speed(&self) -> f32 {
+        if self.fast_forward {
+            self.fast_forward_multiplier()
+        } else if self.slow_motion {
+            self.slow_factor
+        } else {
+            1.0
+        }
+    }
+
+    /// Frames to actually run out of the `due` ones the pacing asks for.
+    pub fn frames(&mut self, due: u32) -> u32 {
+        if !self.paused {
+            return due;
+        }
+        let advance = self.advance;
+        self.advance = false;
+        advance as u32
+    }
+
+    /// Short status for the screen, or nothing at normal speed.
+    pub fn indicator(&self) -> Option<String> {
+        if self.paused {
+            Some(String::from("Paused"))
+        } else if self.fast_forward {
+            Some(format!(">> {}x", self.fast_forward_multiplier()))
+        } else if self.slow_motion {
+            Some(format!("Slow {}x", self.slow_factor))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+    use crate::coverage::Coverage;
+    use crate::cpu::Cpu;
+    use crate::graphics::Graphics;
+    use crate::input::Input;
+    use crate::mmu::Mmu;
+
+    #[test]
+    fn halted_cpu_records_no_coverage() {
+        let mut mmu = Mmu::new();
+        mmu.load_rom(vec![0x00, 0xFD]);
+        let mut cpu = Cpu::new();
+        let mut graphics = Graphics::new();
+        let mut coverage = Coverage::new();
+        let mut scheduler = Scheduler::new(10);
+        for _ in 0..3 {
+            scheduler.run_frame(
+                &mut cpu,
+                &mut mmu,
+                &mut graphics,
+                &Input::new(),
+                &mut coverage,
+            );
+        }
+        assert!(cpu.is_halted());
+        assert_eq!(coverage.hits(0x200), 1);
+        assert_eq!(scheduler.frame(), 3);
+    }
+}
@@ -0,0 +1,87 @@
+use chip8::audio::{Beeper, SampleSource, SAMPLE_RATE};
+use chip8::scheduler::FRAME_RATE;
+
+/// Furthest dynamic rate control will stretch or squeeze a frame of audio.
+const MAX_RATE_ADJUSTMENT: f32 = 0.005;
+/// Most frames `AudioSync` will ask for at once when the queue runs dry.
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Plays a `Beeper` through an SDL callback device.
+pub struct Speaker(pub Beeper);
+
+impl sdl2::audio::AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+/// Paces emulation by the audio device: frames run whenever the queued audio
+/// drops below the target latency. Each frame's audio is stretched or
+/// squeezed slightly so the queue settles at the target instead of drifting
+/// with the difference between the audio clock and 60 Hz.
+pub struct AudioSync {
+    queue: sdl2::audio::AudioQueue<f32>,
+    /// Samples the queue should hold.
+    target: u32,
+    samples: Vec<f32>,
+    /// Emulated frames per real frame; each gets proportionally less audio.
+    speed: f32,
+}
+
+impl AudioSync {
+    pub fn open(
+        audio: &sdl2::AudioSubsystem,
+        latency: std::time::Duration,
+    ) -> Result<AudioSync, String> {
+        let queue = audio.open_queue(
+            None,
+            &sdl2::audio::AudioSpecDesired {
+                freq: Some(SAMPLE_RATE as i32),
+                channels: Some(1),
+                samples: Some(512),
+            },
+        )?;
+        queue.resume();
+
+        Ok(AudioSync {
+            queue,
+            target: (latency.as_secs_f32() * SAMPLE_RATE as f32) as u32,
+            samples: vec![],
+            speed: 1.0,
+        })
+    }
+
+    fn queued(&self) -> u32 {
+        self.queue.size() / std::mem::size_of::<f32>() as u32
+    }
+
+    /// Runs frames `speed` times as fast as real time by giving each one
+    /// less audio.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    fn samples_per_frame(&self) -> f32 {
+        SAMPLE_RATE as f32 / FRAME_RATE as f32 / self.speed
+    }
+
+    /// Frames to emulate to top the queue back up to the target latency.
+    pub fn frames_due(&self) -> u32 {
+        let missing = self.target.saturating_sub(self.queued());
+        let limit = MAX_CATCH_UP_FRAMES * (self.speed.ceil() as u32).max(1);
+        ((missing as f32 / self.samples_per_frame()).ceil() as u32).min(limit)
+    }
+
+    /// Generates and queues one frame of audio from `source`.
+    pub fn push_frame(&mut self, source: &mut impl SampleSource) -> Result<(), String> {
+        let fill = self.queued() as f32 / self.target.max(1) as f32;
+        let adjustment = (1.0 - fill).clamp(-1.0, 1.0) * MAX_RATE_ADJUSTMENT;
+        let count = (self.samples_per_frame() * (1.0 + adjustment)).round();
+
+        self.samples.resize(count as usize, 0.0);
+        source.fill(&mut self.samples);
+        self.queue.queue_audio(&self.samples)
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::graphics::Graphics;
+use crate::machine::Machine;
+use crate::mmu::Mmu;
+use crate::scheduler::Scheduler;
+
+/// Reads back the state the machine's `save_state` methods write.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], Chip8Error> {
+        if count > self.data.len() {
+            return Err(Chip8Error::new("Snapshot is truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Chip8Error> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Chip8Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Chip8Error> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// The whole machine at one instant, deflated. Observers such as the heatmap
+/// and coverage are not part of it.
+pub struct Snapshot {
+    compressed: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn capture(scheduler: &Scheduler, cpu: &Cpu, mmu: &Mmu, graphics: &Graphics) -> Snapshot {
+        let mut state = Vec::new();
+        scheduler.save_state(&mut state);
+        cpu.save_state(&mut state);
+        mmu.save_state(&mut state);
+        graphics.save_state(&mut state);
+        Snapshot {
+            compressed: miniz_oxide::deflate::compress_to_vec(&state, 1),
+        }
+    }
+
+    pub fn restore(
+        &self,
+        scheduler: &mut Scheduler,
+        cpu: &mut Cpu,
+        mmu: &mut Mmu,
+        graphics: &mut Graphics,
+    ) -> Result<(), Chip8Error> {
+        let state = miniz_oxide::inflate::decompress_to_vec(&self.compressed)
+            .map_err(|_| Chip8Error::new("Snapshot is corrupt"))?;
+        let mut reader = StateReader::new(&state);
+        scheduler.load_state(&mut reader)?;
+        cpu.load_state(&mut reader)?;
+        mmu.load_state(&mut reader)?;
+        graphics.load_state(&mut reader)
+    }
+
+    /// Bytes the snapshot occupies.
+    pub fn size(&self) -> usize {
+        self.compressed.len()
+    }
+}
+
+/// Snapshots taken every `interval` frames, oldest dropped first once either
+/// `depth` snapshots or `budget` bytes are held.
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    budget: usize,
+    depth: usize,
+    interval: u32,
+    since_capture: u32,
+    used: usize,
+}
+
+impl Rewind {
+    pub fn new(budget: usize, depth: usize, interval: u32) -> Rewind {
+        Rewind {
+            snapshots: VecDeque::new(),
+            budget,
+            depth,
+            interval: interval.max(1),
+            since_capture: 0,
+            used: 0,
+        }
+    }
+
+    /// Called after every frame, takes a snapshot when one is due.
+    pub fn record(&mut self, machine: &Machine) {
+        self.since_capture += 1;
+        if self.since_capture < self.interval {
+            return;
+        }
+        self.since_capture = 0;
+
+        let snapshot = machine.snapshot();
+        self.used += snapshot.size();
+        self.snapshots.push_back(snapshot);
+
+        while self.snapshots.len() > self.depth || self.used > self.budget {
+            match self.snapshots.pop_front() {
+                Some(oldest) => self.used -= oldest.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Goes back one snapshot, `interval` frames. Returns false once the
+    /// history is used up.
+    pub fn step_back(&mut self, machine: &mut Machine) -> Result<bool, Chip8Error> {
+        // Frames have run since the newest snapshot, so going back to it is
+        // a step of its own.
+        if self.since_capture > 0 {
+            if let Some(newest) = self.snapshots.back() {
+                machine.restore(newest)?;
+                self.since_capture = 0;
+                return Ok(true);
+            }
+        }
+
+        // The newest snapshot is the current state, so restore the one
+        // before it but keep it as the new newest.
+        if self.snapshots.len() < 2 {
+            return Ok(false);
+        }
+        if let Some(newest) = self.snapshots.pop_back() {
+            self.used -= newest.size();
+        }
+        self.since_capture = 0;
+        match self.snapshots.back() {
+            Some(snapshot) => {
+                machine.restore(snapshot)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Forgets all history, for when a different ROM is loaded.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.used = 0;
+        self.since_capture = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Frames of history held.
+    pub fn frames(&self) -> u64 {
+        self.snapshots.len().saturating_sub(1) as u64 * self.interval as u64
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Register;
+
+    /// Counts frames in V0: V0 += 1, wait for the next frame, repeat.
+    fn counter() -> Machine {
+        Machine::builder()
+            .rom(&[
+                0x70, 0x01, 0x61, 0x01, 0xF1, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x06, 0x12, 0x00,
+            ])
+            .seed(1)
+            .build()
+            .unwrap()
+    }
+
+    fn count(machine: &Machine) -> u8 {
+        machine.cpu().reg(Register::V0)
+    }
+
+    #[test]
+    fn restore_returns_to_captured_state() {
+        let mut machine = counter();
+        machine.run_frame();
+        let snapshot = machine.snapshot();
+        for _ in 0..5 {
+            machine.run_frame();
+        }
+        assert_eq!(count(&machine), 6);
+        machine.restore(&snapshot).unwrap();
+        assert_eq!(count(&machine), 1);
+        machine.run_frame();
+        assert_eq!(count(&machine), 2);
+    }
+
+    #[test]
+    fn step_back_returns_to_newest_snapshot_first() {
+        let mut machine = counter();
+        let mut rewind = Rewind::new(usize::MAX, 10, 4);
+        for _ in 0..10 {
+            machine.run_frame();
+            rewind.record(&machine);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(count(&machine), 10);
+
+        assert!(rewind.step_back(&mut machine).unwrap());
+        assert_eq!(count(&machine), 8);
+        assert_eq!(rewind.len(), 2);
+
+        assert!(rewind.step_back(&mut machine).unwrap());
+        assert_eq!(count(&machine), 4);
+        assert_eq!(rewind.len(), 1);
+        assert!(!rewind.step_back(&mut machine).unwrap());
+    }
+
+    #[test]
+    fn drops_oldest_snapshots_past_depth() {
+        let mut machine = counter();
+        let mut rewind = Rewind::new(usize::MAX, 3, 1);
+        for _ in 0..10 {
+            machine.run_frame();
+            rewind.record(&machine);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.frames(), 2);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use crate::error::Chip8Error;
+
+/// Source location an assembler emitted for a ROM address.
+#[derive(Clone, Debug)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+/// Maps ROM addresses back to assembler source lines.
+///
+/// The file format is one entry per line, `<hex address> <file>:<line>`, for
+/// example `0x0202 game.8o:14`. Blank lines and lines starting with `#` are
+/// ignored.
+pub struct SymbolMap {
+    lines: HashMap<u16, SourceLine>,
+}
+
+impl SymbolMap {
+    pub fn parse(text: &str) -> Result<SymbolMap, Chip8Error> {
+        let mut lines = HashMap::new();
+
+        for (number, entry) in text.lines().enumerate() {
+            let entry = entry.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || Chip8Error::new(&format!("Invalid symbol on line {}", number + 1));
+
+            let (address, location) = entry.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (file, line) = location.trim().rsplit_once(':').ok_or_else(invalid)?;
+
+            let address =
+                u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+            let line = line.parse::<u32>().map_err(|_| invalid())?;
+
+            lines.insert(
+                address,
+                SourceLine {
+                    file: String::from(file),
+                    line,
+                },
+            );
+        }
+
+        Ok(SymbolMap { lines })
+    }
+
+    pub fn load(path: &str) -> Result<SymbolMap, Chip8Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => SymbolMap::parse(&text),
+            Err(e) => Err(Chip8Error::new(&format!(
+                "Failed to read symbols {}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    pub fn lookup(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.get(&address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        let symbols = SymbolMap::parse(
+            "# generated\n\
+             \n\
+             0x0202 game.8o:14\n\
+             2a4\tlib/draw.8o:3\n",
+        )
+        .unwrap();
+        let line = symbols.lookup(0x202).unwrap();
+        assert_eq!((line.file.as_str(), line.line), ("game.8o", 14));
+        let line = symbols.lookup(0x2A4).unwrap();
+        assert_eq!((line.file.as_str(), line.line), ("lib/draw.8o", 3));
+        assert!(symbols.lookup(0x200).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        for text in ["0x200", "0x200 game.8o", "zz game.8o:1", "0x200 game.8o:x"] {
+            assert!(SymbolMap::parse(text).is_err(), "{} was accepted", text);
+        }
+    }
+}
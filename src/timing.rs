@@ -0,0 +1,105 @@
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+/// Machine cycles in one 60 Hz frame of the COSMAC VIP: 262 lines of 14 cycles.
+pub const CYCLES_PER_FRAME: u32 = 3668;
+/// Cycles the CDP1861 steals each frame to DMA 128 lines of 8 bytes.
+pub const DISPLAY_DMA_CYCLES: u32 = 1024;
+/// Cycles the vblank interrupt handler spends on the timers.
+pub const INTERRUPT_CYCLES: u32 = 58;
+/// Cycles the interpreter spends fetching and dispatching every instruction.
+pub const FETCH_CYCLES: u32 = 40;
+/// Extra cycles when a skip instruction skips.
+pub const SKIP_CYCLES: u32 = 4;
+
+/// Charges instructions the machine cycles the original COSMAC VIP
+/// interpreter spends on them. Costs are approximations from counting the
+/// 1802 instructions in each interpreter routine.
+#[derive(Clone, Default)]
+pub struct VipTiming {
+    /// Cycles an instruction overran into the next frame.
+    carry: u32,
+}
+
+impl VipTiming {
+    pub fn new() -> VipTiming {
+        VipTiming { carry: 0 }
+    }
+
+    /// Cycles already owed to the next frame.
+    pub fn carry(&self) -> u32 {
+        self.carry
+    }
+
+    pub fn set_carry(&mut self, carry: u32) {
+        self.carry = carry;
+    }
+
+    /// Cycles left for the interpreter in the frame that is starting, after
+    /// display DMA, the interrupt handler and any overrun from the last frame.
+    pub fn start_frame(&mut self) -> u32 {
+        let budget =
+            (CYCLES_PER_FRAME - DISPLAY_DMA_CYCLES - INTERRUPT_CYCLES).saturating_sub(self.carry);
+        self.carry = self
+            .carry
+            .saturating_sub(CYCLES_PER_FRAME - DISPLAY_DMA_CYCLES - INTERRUPT_CYCLES);
+        budget
+    }
+
+    /// Carries cycles over into the next frame.
+    pub fn defer(&mut self, cycles: u32) {
+        self.carry += cycles;
+    }
+
+    /// Cost of `instruction` given the cpu state before it runs.
+    pub fn cycles(instruction: &Instruction, cpu: &Cpu) -> u32 {
+        FETCH_CYCLES
+            + match *instruction {
+                // The display buffer is cleared one byte per loop iteration
+                Instruction::Cls => 24 + 256 * 8,
+                Instruction::Ret => 10,
+                Instruction::Jmp(_) => 12,
+                Instruction::Call(_) => 26,
+                Instruction::SkipInstructionEqual(..)
+                | Instruction::SkipInstructionNotEqual(..) => 10,
+                Instruction::LoadConstant(..) => 6,
+                Instruction::Add(..) => 10,
+                // Arithmetic runs through a small routine built in RAM
+                Instruction::LoadRegister(..)
+                | Instruction::OrRegister(..)
+                | Instruction::AndRegister(..)
+                | Instruction::XorRegister(..)
+                | Instruction::AddRegister(..)
+                | Instruction::SubRegister(..)
+                | Instruction::Shr(..)
+                | Instruction::Subn(..)
+                | Instruction::Shl(..) => 44,
+                Instruction::SkipInstructionRegisterEqual(..)
+                | Instruction::SkipInstructionRegisterNotEqual(..) => 14,
+                Instruction::LoadIndex(_) => 12,
+                Instruction::JumpV0(_) => 22,
+                Instruction::Random(..) => 36,
+                // Every row is shifted into place one bit at a time when the
+                // sprite is not byte aligned
+                Instruction::Draw(x, _, rows) => {
+                    let shift = (cpu.reg(x) & 7) as u32;
+                    68 + rows as u32 * (34 + 4 * shift)
+                }
+                Instruction::SkipIfPressed(_) | Instruction::SkipIfNotPressed(_) => 14,
+                Instruction::LoadDelayTimer(_)
+                | Instruction::StoreDelayTimer(_)
+                | Instruction::StoreSoundTimer(_) => 10,
+                Instruction::WaitForKeyPress(_) => 20,
+                Instruction::AddIndex(_) | Instruction::LoadSpriteIndex(_) => 20,
+                // Repeated subtraction of 100 and 10
+                Instruction::StoreBcd(x) => {
+                    36 + 8 * (cpu.reg(x) as u32 / 10 % 10 + cpu.reg(x) as u32 / 100)
+                }
+                Instruction::StoreRegisters(x) | Instruction::LoadRegisters(x) => {
+                    14 + 14 * (x.to_index() as u32 + 1)
+                }
+                // Not part of the VIP interpreter
+                _ => 0,
+            }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::cpu::Register;
+use crate::error::Chip8Error;
+use crate::graphics::{Graphics, Resolution};
+use crate::input::Input;
+use crate::mmu::PROGRAM_START;
+use crate::timing::CYCLES_PER_FRAME;
+
+const RAM_SIZE: usize = 4096;
+/// Bytes the CDP1861 fetches per scanline.
+const BYTES_PER_LINE: usize = Resolution::LOW.width / 8;
+/// The interpreter occupies the memory below the CHIP-8 program.
+const INTERPRETER_SIZE: usize = PROGRAM_START as usize;
+
+/// Machine cycles per CDP1861 scanline.
+const CYCLES_PER_LINE: u32 = 14;
+/// Scanlines the CDP1861 fetches with DMA, 8 bytes each.
+const DISPLAY_LINES: std::ops::Range<u32> = 64..192;
+/// The CPU runs for this many cycles of each display line before DMA starts.
+const DMA_START: u32 = 6;
+/// The interrupt is raised two lines before the display starts.
+const INTERRUPT_LINES: std::ops::Range<u32> = 62..64;
+
+/// Everything on the VIP board except the CPU.
+struct Board {
+    ram: Vec<u8>,
+    display_enabled: bool,
+    key_latch: u8,
+    keys: [bool; 16],
+    line: u32,
+}
+
+impl Bus for Board {
+    fn read(&mut self, address: u16) -> u8 {
+        // 4 KiB of RAM is mirrored through the lower 32 KiB, and there is no
+        // monitor ROM above it
+        if address < 0x8000 {
+            self.ram[address as usize % RAM_SIZE]
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            self.ram[address as usize % RAM_SIZE] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_enabled = false,
+            2 => self.key_latch = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_enabled = true;
+        }
+        0
+    }
+
+    fn flag(&self, n: u8) -> bool {
+        match n {
+            // Set for the four lines before the end of each display window
+            1 => (60..64).contains(&self.line) || (188..192).contains(&self.line),
+            3 => self.keys[self.key_latch as usize],
+            _ => false,
+        }
+    }
+}
+
+/// A COSMAC VIP running the original CHIP-8 interpreter, as a reference for
+/// `Cpu::step`.
+pub struct Vip {
+    cpu: Cdp1802,
+    board: Board,
+    scanlines: Vec<u8>,
+    /// Cycles the last instruction of a frame ran into the next one.
+    overrun: u32,
+}
+
+impl Vip {
+    /// `interpreter` is a dump of the 512 byte VIP CHIP-8 interpreter.
+    pub fn new(interpreter: &[u8], rom: &[u8]) -> Result<Vip, Chip8Error> {
+        if interpreter.len() > INTERPRETER_SIZE {
+            return Err(Chip8Error::new(
+                "Interpreter image is larger than 512 bytes",
+            ));
+        }
+        if INTERPRETER_SIZE + rom.len() > RAM_SIZE {
+            return Err(Chip8Error::new("Rom does not fit in VIP memory"));
+        }
+
+        let mut ram = vec![0; RAM_SIZE];
+        ram[..interpreter.len()].copy_from_slice(interpreter);
+        ram[INTERPRETER_SIZE..INTERPRETER_SIZE + rom.len()].copy_from_slice(rom);
+
+        // The monitor hands over with the top of memory in R1
+        let mut cpu = Cdp1802::new();
+        cpu.set_reg(1, RAM_SIZE as u16 - 1);
+
+        Ok(Vip {
+            cpu,
+            board: Board {
+                ram,
+                display_enabled: false,
+                key_latch: 0,
+                keys: [false; 16],
+                line: 0,
+            },
+            scanlines: vec![0; DISPLAY_LINES.len() * BYTES_PER_LINE],
+            overrun: 0,
+        })
+    }
+
+    /// Runs the 262 scanlines of one 60 Hz frame.
+    pub fn run_frame(&mut self, input: &Input) {
+        for key in 0..16 {
+            self.board.keys[key] = input.key_pressed(key as u8);
+        }
+
+        let mut cycle = self.overrun;
+        let mut dma_line = None;
+        let mut interrupted = false;
+
+        while cycle < CYCLES_PER_FRAME {
+            let line = cycle / CYCLES_PER_LINE;
+            self.board.line = line;
+
+            if self.board.display_enabled
+                && DISPLAY_LINES.contains(&line)
+                && cycle % CYCLES_PER_LINE >= DMA_START
+                && dma_line != Some(line)
+            {
+                dma_line = Some(line);
+                let start = (line - DISPLAY_LINES.start) as usize * BYTES_PER_LINE;
+                for byte in start..start + BYTES_PER_LINE {
+                    self.scanlines[byte] = self.cpu.dma_out(&mut self.board);
+                }
+                cycle += BYTES_PER_LINE as u32;
+                continue;
+            }
+
+            if self.board.display_enabled && !interrupted && INTERRUPT_LINES.contains(&line) {
+                if let Some(cycles) = self.cpu.interrupt() {
+                    interrupted = true;
+                    cycle += cycles;
+                    continue;
+                }
+            }
+
+            cycle += self.cpu.step(&mut self.board);
+        }
+
+        self.overrun = cycle - CYCLES_PER_FRAME;
+    }
+
+    /// Copies the picture into `graphics`. The interpreter shows each row of
+    /// pixels on four consecutive scanlines.
+    pub fn render(&self, graphics: &mut Graphics) {
+        if graphics.resolution() != Resolution::LOW {
+            graphics.set_resolution(Resolution::LOW);
+        }
+
+        let repeat = DISPLAY_LINES.len() / Resolution::LOW.height;
+        for y in 0..Resolution::LOW.height {
+            let row = &self.scanlines[y * repeat * BYTES_PER_LINE..];
+            for x in 0..Resolution::LOW.width {
+                graphics.set_pixel(x, y, row[x / 8] & (0x80 >> (x % 8)) != 0);
+            }
+        }
+    }
+
+    /// The VIP beeps while Q is set.
+    pub fn sound(&self) -> bool {
+        self.cpu.q()
+    }
+
+    /// The interpreter keeps V0-VF at the end of the page below the display.
+    pub fn reg(&self, register: Register) -> u8 {
+        self.board.ram[RAM_SIZE - 0x110 + register.to_index()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vip;
+    use crate::cpu::{Cpu, Register};
+    use crate::graphics::Graphics;
+    use crate::input::Input;
+    use crate::mmu::Mmu;
+    use crate::quirks::QuirksPreset;
+
+    /// Path to a dump of the VIP interpreter, which is not distributed with
+    /// the emulator.
+    const INTERPRETER_VAR: &str = "CHIP8_VIP_INTERPRETER";
+
+    #[test]
+    fn registers_match_cpu() {
+        let Ok(path) = std::env::var(INTERPRETER_VAR) else {
+            eprintln!("{} is not set, skipping", INTERPRETER_VAR);
+            return;
+        };
+        let interpreter = std::fs::read(path).unwrap();
+
+        // Arithmetic with carries and borrows, shifts, BCD and loads, then
+        // loop forever
+        let rom = [
+            0x60, 0xFE, 0x61, 0x03, 0x80, 0x14, 0x62, 0x0A, 0x82, 0x15, 0x83, 0x26, 0x84, 0x0E,
+            0x85, 0x27, 0xA3, 0x00, 0x72, 0xF5, 0xF2, 0x33, 0xF2, 0x65, 0x86, 0x31, 0x12, 0x1A,
+        ];
+
+        let input = Input::new();
+        let mut vip = Vip::new(&interpreter, &rom).unwrap();
+        for _ in 0..10 {
+            vip.run_frame(&input);
+        }
+
+        let mut mmu = Mmu::new();
+        mmu.load_rom(rom.to_vec());
+        let mut cpu = Cpu::with_quirks(QuirksPreset::Chip8.quirks());
+        let mut graphics = Graphics::new();
+        while cpu.pc() != 0x21A {
+            cpu.step(&mut mmu, &mut graphics, &input);
+        }
+
+        for index in 0..16 {
+            let register = Register::from_index(index);
+            assert_eq!(vip.reg(register), cpu.reg(register), "{}", register);
+        }
+    }
+}